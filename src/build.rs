@@ -1,12 +1,14 @@
-use std::collections::{HashSet, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
+use rand::{Rng, RngCore, SeedableRng};
 use rand::rngs::SmallRng;
 
 use crate::layer::Layer;
 use crate::utils::region::Region;
+use crate::utils::disjoint_sets::DisjointSets;
 use crate::visible_area::visible_area;
-use crate::maze::{Maze, LazyCellInfo};
-use crate::geometry::{Dir, DIRECTIONS};
-use crate::generation::generate;
+use crate::maze::{Maze, CellInfo, LazyCellInfo};
+use crate::geometry::{Dir, Topology};
+use crate::generation::{braid, CarvingAlgorithm, GrowingTree, GenerationParams};
 use crate::traversal;
 
 fn reachable_cells(
@@ -22,7 +24,7 @@ fn reachable_cells(
     let mut queue = VecDeque::new();
     queue.push_back(from);
     while let Some(c) = queue.pop_front() {
-        for &dir in &DIRECTIONS {
+        for &dir in layer.directions(c) {
             let to = c + dir;
             if region.cells().contains(&to)
                 && layer.passable(c, dir)
@@ -36,6 +38,8 @@ fn reachable_cells(
     reached
 }
 
+/// Copies passages between the cells of `region`. Passages leading out of it
+/// are left to `split_passages_out`.
 fn copy_region(
     src: &Layer<LazyCellInfo>,
     pos: (i32, i32),
@@ -46,8 +50,8 @@ fn copy_region(
     for &cell in region.cells() {
         if src.has(cell) {
             assert!(dst.has(cell));
-            for &dir in &DIRECTIONS {
-                if src.passable(cell, dir) {
+            for &dir in src.directions(cell) {
+                if src.passable(cell, dir) && region.cells().contains(&(cell + dir)) {
                     dst.join(cell, dir);
                 }
             }
@@ -61,6 +65,21 @@ fn copy_region(
     }
 }
 
+/// Passages of `layer` leading out of `region` sorted by their cells.
+fn passages_out(
+    layer: &Layer<LazyCellInfo>,
+    region: &Region
+) -> Vec<((i32, i32), Dir)> {
+    let mut passages: Vec<_> = region.cells().iter()
+        .flat_map(|&cell| layer.directions(cell).iter().map(move |&dir| (cell, dir)))
+        .filter(|&(cell, dir)| {
+            layer.passable(cell, dir) && !region.cells().contains(&(cell + dir))
+        })
+        .collect();
+    passages.sort_by_key(|&(cell, dir)| (cell, cell + dir));
+    passages
+}
+
 fn carve_path<I: Default>(layer: &mut Layer<I>, start: (i32, i32), path: &[Dir]) {
     let mut c = start;
     for &dir in path {
@@ -69,82 +88,636 @@ fn carve_path<I: Default>(layer: &mut Layer<I>, start: (i32, i32), path: &[Dir])
     }
 }
 
+/// Whether `to` can be reached from `from` without stepping on `portals`.
+/// Stepping on the source cell of an entrance takes the player back to the
+/// source layer, so the ways through it don't count.
+fn reachable_avoiding<I: Default>(
+    layer: &Layer<I>,
+    from: (i32, i32),
+    to: (i32, i32),
+    portals: &[(i32, i32)],
+) -> bool {
+    let mut reached = HashSet::new();
+    reached.insert(from);
+    let mut queue = VecDeque::new();
+    queue.push_back(from);
+    while let Some(c) = queue.pop_front() {
+        for &dir in layer.directions(c) {
+            let next = c + dir;
+            if !layer.passable(c, dir) {
+                continue;
+            }
+            if next == to {
+                return true;
+            }
+            if !portals.contains(&next) && reached.insert(next) {
+                queue.push_back(next);
+            }
+        }
+    }
+    false
+}
+
+/// Splits the cells of a layer into parts which stay connected if the layer
+/// is carved outside of `region`: the cells of `region` connected by
+/// passages and the cells outside of it connected by any means.
+fn label_parts(
+    layer: &Layer<LazyCellInfo>,
+    region: &Region
+) -> (HashMap<(i32, i32), usize>, usize) {
+    let mut labels = HashMap::new();
+    let mut count = 0;
+    for start in layer.cells() {
+        if labels.contains_key(&start) {
+            continue;
+        }
+        let inside = region.cells().contains(&start);
+        labels.insert(start, count);
+        let mut queue = VecDeque::new();
+        queue.push_back(start);
+        while let Some(c) = queue.pop_front() {
+            for &dir in layer.directions(c) {
+                let to = c + dir;
+                if layer.has(to)
+                    && region.cells().contains(&to) == inside
+                    && (!inside || layer.passable(c, dir))
+                    && !labels.contains_key(&to)
+                {
+                    labels.insert(to, count);
+                    queue.push_back(to);
+                }
+            }
+        }
+        count += 1;
+    }
+    (labels, count)
+}
+
+/// Splits the passages leading out of the copied region into the ones
+/// connecting different parts of the new layer (see `label_parts`) and
+/// the rest. Joining every passage before carving would make carving
+/// split the cells outside of the region between them, so most of those
+/// cells would end up behind the player.
+///
+/// `preferred` passages are considered first, and the first of them is
+/// always a connecting one. Returns `None` if the parts can't be connected,
+/// that is, some cells of the new layer would be unreachable.
+#[allow(clippy::type_complexity)]
+fn split_passages_out(
+    layer: &Layer<LazyCellInfo>,
+    region: &Region,
+    preferred: &[((i32, i32), Dir)],
+    passages: &[((i32, i32), Dir)],
+) -> Option<(Vec<((i32, i32), Dir)>, Vec<((i32, i32), Dir)>)> {
+    let (labels, count) = label_parts(layer, region);
+    let mut parts = DisjointSets::new(count);
+    let mut connecting = Vec::new();
+    let mut others = Vec::new();
+    let passages = preferred.iter()
+        .chain(passages.iter().filter(|passage| !preferred.contains(passage)));
+    for &(cell, dir) in passages {
+        let (a, b) = (labels[&cell], labels[&(cell + dir)]);
+        if parts.equiv(a, b) {
+            others.push((cell, dir));
+        } else {
+            parts.union(a, b);
+            connecting.push((cell, dir));
+        }
+    }
+    // A spanning tree of the parts has one edge less than there are parts.
+    if connecting.len() + 1 < count {
+        return None;
+    }
+    Some((connecting, others))
+}
+
+
+/// A transition leading into a new layer.
+struct Entrance {
+    source_layer_index: usize,
+    source_coord: (i32, i32),
+    back: Dir,
+    escape: (i32, i32),
+    escape_dir: Dir,
+}
+
+/// A layer with the areas around the transitions already copied from
+/// the source layers. Carving it needs nothing else from the maze, so
+/// several jobs can run at the same time.
+struct LayerJob {
+    /// The first entrance is the one the layer is traversed from.
+    entrances: Vec<Entrance>,
+    /// Cells carving starts from. Growing trees start from the last one.
+    spawn_points: Vec<(i32, i32)>,
+    /// Passages out of the copied region which are joined after carving,
+    /// unless the cells behind them are reachable by then. Those cells are
+    /// left as dead ends.
+    late_passages: Vec<((i32, i32), Dir)>,
+    layer: Layer<LazyCellInfo>,
+    region: Region,
+    params: GenerationParams,
+    visibility_radius: i32,
+}
+
+struct CarvedLayer {
+    entrances: Vec<Entrance>,
+    layer: Layer<LazyCellInfo>,
+    info: traversal::Info,
+}
+
+impl LayerJob {
+    fn carve(
+        self,
+        shape: &[(i32, i32)],
+        algorithm: &dyn CarvingAlgorithm<LazyCellInfo>,
+        rng: &mut dyn RngCore,
+    ) -> CarvedLayer {
+        let LayerJob {
+            entrances, spawn_points, late_passages, mut layer, region, params,
+            visibility_radius
+        } = self;
+        let params = &params;
+
+        let dfs = if algorithm.is_legacy() {
+            // Legacy layers are carved from the escapes and then once more
+            // from every cell to get rid of the parts blocked out by the
+            // copied region, as it used to be done.
+            algorithm.carve(&mut layer, &spawn_points, region.cells(), params, rng);
+            algorithm.carve(&mut layer, shape, region.cells(), params, rng);
+            traversal::legacy_dfs
+        } else {
+            let late_cells: Vec<_> = late_passages.iter()
+                .map(|&(cell, dir)| cell + dir)
+                .filter(|cell| !spawn_points.contains(cell))
+                .collect();
+            let mut blocked_cells = region.cells().clone();
+            blocked_cells.extend(late_cells.iter().copied());
+            algorithm.carve(&mut layer, &spawn_points, &blocked_cells, params, rng);
+
+            // Some cells can only be reached through the late passages.
+            // The source cell is chosen so that all the other cells are.
+            // Joining a cell which the player can reach already would make
+            // a loop, this happens to the spawn points and to the cells
+            // several late passages lead to.
+            let portals: Vec<_> = entrances.iter().map(|entrance| entrance.source_coord).collect();
+            for &(cell, dir) in &late_passages {
+                if !reachable_avoiding(&layer, cell + dir, cell, &portals) {
+                    layer.join(cell, dir);
+                }
+            }
+            algorithm.carve(&mut layer, &late_cells, region.cells(), params, rng);
+            traversal::dfs
+        };
+        braid(&mut layer, shape.iter().copied(), region.cells(), params, rng);
+
+        // A traversal has a single start, so the layer is traversed from
+        // the first entrance even if the player enters it from the others.
+        let entrance = &entrances[0];
+        let info = dfs(&layer, entrance.source_coord, Some(entrance.back), visibility_radius);
+
+        CarvedLayer { entrances, layer, info }
+    }
+}
+
+
+/// How the cell the player leaves the copied region through is chosen.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum Escape {
+    /// Whichever is found first. With the visible area as the copied region
+    /// it is the one found by the traversal of the source layer, otherwise
+    /// it is the nearest one.
+    #[default]
+    Any,
+    /// The one farthest from the transition.
+    Deepest,
+    /// The one closest to the transition.
+    Nearest,
+    /// The given cell. It must lie ahead of the transition and outside of
+    /// the copied region and its boundary.
+    At((i32, i32)),
+}
+
+/// Describes a layer added by `MazeBuilder::add_layer_at`.
+#[derive(Debug, Clone, Default)]
+pub struct LayerOptions {
+    /// The area copied from the source layer, which must contain the cell
+    /// of the transition. The area visible from that cell if `None`.
+    pub region: Option<Region>,
+    pub escape: Escape,
+    /// Carving parameters of the new layer. The builder's ones if `None`.
+    pub params: Option<GenerationParams>,
+}
+
+/// Cells which lie ahead of `source` in the traversal of the layer outside
+/// of `region` and its boundary, so they can be used as escapes.
+fn escape_candidates(
+    layer: &Layer<LazyCellInfo>,
+    info: &traversal::Info,
+    source: (i32, i32),
+    region: &Region,
+) -> Vec<(i32, i32)> {
+    let mut candidates = Vec::new();
+    let mut stack = vec![source];
+    while let Some(cell) = stack.pop() {
+        if !region.cells().contains(&cell) && !region.boundary().contains(&cell) {
+            candidates.push(cell);
+        }
+        for &dir in layer.directions(cell) {
+            let to = cell + dir;
+            let is_child = info.coords.get(&to)
+                .is_some_and(|to_info| to_info.came_from == Some(dir.opposite()));
+            if layer.passable(cell, dir) && is_child {
+                stack.push(to);
+            }
+        }
+    }
+    candidates
+}
+
+
+/// Why a maze couldn't be built. Most failures depend on how the layers
+/// happened to be carved, so generators retry them with other random numbers.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GenerationError {
+    /// The layer has fewer dead ends which can be turned into transitions
+    /// than needed.
+    NotEnoughLeafEscapables { layer: usize, found: usize, needed: usize, shape_size: usize },
+    /// The deepest dead end which can be turned into a transition is where
+    /// the player enters the layer.
+    DeepestPointAtStart { layer: usize, shape_size: usize },
+    /// The cell can't be reached in the layer.
+    UnreachableCell { layer: usize, cell: (i32, i32) },
+    /// The player enters the layer at the cell, so there is no way back
+    /// from a transition there.
+    TransitionAtStart { layer: usize, cell: (i32, i32) },
+    /// The player can't leave the area visible from the cell without
+    /// coming back to it.
+    NotEscapable { layer: usize, cell: (i32, i32) },
+    /// The copied region doesn't contain the cell.
+    CellOutsideRegion { layer: usize, cell: (i32, i32) },
+    /// Copying the region around the cell would make some of its cells
+    /// unreachable.
+    RegionBreaksLayer { layer: usize, cell: (i32, i32) },
+    /// No cell satisfies `LayerOptions::escape`.
+    NoEscape { layer: usize, cell: (i32, i32) },
+    /// The area copied around the cell touches the area copied for another
+    /// transition into the same layer.
+    RegionsTouch { layer: usize, cell: (i32, i32) },
+    /// A single copied region was given for the areas around several cells.
+    RegionForSeveralCells { cells: usize },
+    /// Entering the cell already leads to another layer.
+    TransitionsOverlap { layer: usize, cell: (i32, i32) },
+    /// Some cells of the new layer would be unreachable.
+    DisconnectedLayer { shape_size: usize },
+    /// The level generator didn't like the maze.
+    Rejected(&'static str),
+    /// A level generator ran out of attempts or time. There is no last
+    /// error if no attempts were made.
+    GaveUp { attempts: u32, last_error: Option<Box<GenerationError>> },
+    /// A step of a level generator failed.
+    Step { step: &'static str, error: Box<GenerationError> },
+}
 
-#[derive(Debug, Default)]
-pub struct GenerationError {}
+impl GenerationError {
+    /// Tells which step of a level generator failed.
+    pub fn in_step(self, step: &'static str) -> GenerationError {
+        GenerationError::Step { step, error: Box::new(self) }
+    }
+}
 
 impl std::fmt::Display for GenerationError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Generation error")
+        use GenerationError::*;
+        match self {
+            NotEnoughLeafEscapables { layer, found, needed, shape_size } => write!(
+                f, "layer {} of {} cells has {} leaf escapables, {} needed",
+                layer, shape_size, found, needed
+            ),
+            DeepestPointAtStart { layer, shape_size } => write!(
+                f, "the deepest leaf escapable of layer {} of {} cells is at its start",
+                layer, shape_size
+            ),
+            UnreachableCell { layer, cell } =>
+                write!(f, "cell {:?} of layer {} is unreachable", cell, layer),
+            TransitionAtStart { layer, cell } =>
+                write!(f, "cell {:?} is the start of layer {}", cell, layer),
+            NotEscapable { layer, cell } =>
+                write!(f, "cell {:?} of layer {} is not escapable", cell, layer),
+            CellOutsideRegion { layer, cell } => write!(
+                f, "the copied region doesn't contain cell {:?} of layer {}", cell, layer
+            ),
+            RegionBreaksLayer { layer, cell } => write!(
+                f, "copying the region around cell {:?} of layer {} breaks it apart",
+                cell, layer
+            ),
+            NoEscape { layer, cell } => write!(
+                f, "no escape matches the options at cell {:?} of layer {}", cell, layer
+            ),
+            RegionsTouch { layer, cell } => write!(
+                f, "the region around cell {:?} of layer {} touches another region",
+                cell, layer
+            ),
+            RegionForSeveralCells { cells } => write!(
+                f, "a single copied region is given for {} cells", cells
+            ),
+            TransitionsOverlap { layer, cell } => write!(
+                f, "cell {:?} of layer {} already leads to another layer", cell, layer
+            ),
+            DisconnectedLayer { shape_size } => write!(
+                f, "a new layer of {} cells would have unreachable cells", shape_size
+            ),
+            Rejected(reason) => write!(f, "rejected: {}", reason),
+            GaveUp { attempts, last_error: Some(error) } =>
+                write!(f, "gave up after {} attempts, the last error: {}", attempts, error),
+            GaveUp { attempts, last_error: None } =>
+                write!(f, "gave up after {} attempts", attempts),
+            Step { step, error } => write!(f, "{}: {}", step, error),
+        }
+    }
+}
+
+impl std::error::Error for GenerationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GenerationError::Step { error, .. } => Some(error.as_ref()),
+            GenerationError::GaveUp { last_error: Some(error), .. } => Some(error.as_ref()),
+            _ => None,
+        }
     }
 }
-impl std::error::Error for GenerationError {}
 
 
 pub struct MazeBuilder<'r> {
     maze: Option<Maze>,
 
     shape: Vec<(i32, i32)>,
+    topology: Topology,
+    visibility_radius: i32,
+    params: GenerationParams,
     rng: &'r mut SmallRng,
+    algorithm: &'static dyn CarvingAlgorithm<LazyCellInfo>,
 }
 
 impl<'r> MazeBuilder<'r> {
-    pub fn new(shape: Vec<(i32, i32)>, rng: &mut SmallRng) -> MazeBuilder {
+    /// Transitions are placed where the player doesn't see them, so all
+    /// the layers are built for the given visibility radius.
+    pub fn new(
+        shape: Vec<(i32, i32)>,
+        params: GenerationParams,
+        visibility_radius: i32,
+        rng: &mut SmallRng
+    ) -> MazeBuilder<'_> {
         MazeBuilder{
-            maze: None, shape,
-            rng
+            maze: None, shape, params,
+            topology: Topology::Square,
+            visibility_radius,
+            rng,
+            algorithm: &GrowingTree(),
         }
     }
 
+    /// Sets the algorithm used for carving all the layers added after this call.
+    pub fn set_carving_algorithm(
+        &mut self,
+        algorithm: &'static dyn CarvingAlgorithm<LazyCellInfo>
+    ) {
+        self.algorithm = algorithm;
+    }
+
+    /// Sets the topology of all the layers. Must be called before
+    /// the first layer is generated.
+    pub fn set_topology(&mut self, topology: Topology) {
+        assert!(self.maze.is_none(), "Topology can't be changed after generation started");
+        self.topology = topology;
+    }
+
     pub fn into_maze(self) -> Maze {
         self.maze.unwrap()
     }
 
-    fn traversal_info(&mut self, src_layer: usize) -> &traversal::Info {
-        &self.maze.as_mut().unwrap().maze_layer(src_layer).info
+    /// The traversal of the layer from where the player enters it, which
+    /// tells where transitions can be added with `add_layer_at`.
+    pub fn traversal_info(&self, src_layer: usize) -> &traversal::Info {
+        &self.maze.as_ref().unwrap().maze_layer(src_layer).info
     }
 
-    fn add_layer(
-        &mut self,
+    /// Finds the way the player leaves the area copied around `source_coord`.
+    /// Returns the transition along with the area and the passage leading
+    /// out of it on the way to the escape.
+    #[allow(clippy::type_complexity)]
+    fn find_entrance(
+        &self,
         source_layer_index: usize,
         source_coord: (i32, i32),
-    ) -> usize {
-        let maze = self.maze.as_mut().unwrap();
-        let maze_layer = maze.maze_layer(source_layer_index);
+        options: &LayerOptions,
+    ) -> Result<(Entrance, Region, ((i32, i32), Dir)), GenerationError> {
+        let maze_layer = self.maze.as_ref().unwrap().maze_layer(source_layer_index);
         let info = &maze_layer.info;
-        let back = info.coords[&source_coord].came_from.unwrap();
+        let (layer, cell) = (source_layer_index, source_coord);
+        let coord_info = info.coords.get(&source_coord)
+            .ok_or(GenerationError::UnreachableCell { layer, cell })?;
+        let back = coord_info.came_from.ok_or(GenerationError::TransitionAtStart { layer, cell })?;
 
-        let escape = info.coords[&source_coord].escapable.unwrap();
-        let path_to_escape = traversal::get_path_to(source_coord, escape, &info);
+        // The traversal has already checked the visible area.
+        let region = match &options.region {
+            Some(region) => {
+                if !region.cells().contains(&source_coord) {
+                    return Err(GenerationError::CellOutsideRegion { layer, cell });
+                }
+                if !traversal::stays_connected(&maze_layer.layer, source_coord, region) {
+                    return Err(GenerationError::RegionBreaksLayer { layer, cell });
+                }
+                region.clone()
+            },
+            None if coord_info.escapable.is_some() =>
+                visible_area(self.topology, self.visibility_radius, source_coord),
+            None => return Err(GenerationError::NotEscapable { layer, cell }),
+        };
+        let escape = match options.escape {
+            Escape::Any if options.region.is_none() => coord_info.escapable.unwrap(),
+            escape => {
+                let candidates = escape_candidates(&maze_layer.layer, info, source_coord, &region);
+                let depth = |cell: &&(i32, i32)| info.coords[*cell].depth;
+                match escape {
+                    Escape::Any | Escape::Nearest => candidates.iter().min_by_key(depth).copied(),
+                    Escape::Deepest => candidates.iter().max_by_key(depth).copied(),
+                    Escape::At(cell) => Some(cell).filter(|cell| candidates.contains(cell)),
+                }.ok_or(GenerationError::NoEscape { layer, cell })?
+            },
+        };
+        let path_to_escape = traversal::get_path_to(source_coord, escape, info);
         let escape_dir = *path_to_escape.first().unwrap();
 
-        let mut new_layer = Layer::from_shape(&self.shape);
-        let region_to_copy = visible_area().shifted_by(source_coord);
-        copy_region(&maze_layer.layer, source_coord, source_layer_index, &mut new_layer, &region_to_copy);
+        let mut cell = source_coord;
+        let exit = path_to_escape.iter().find_map(|&dir| {
+            if region.cells().contains(&(cell + dir)) {
+                cell = cell + dir;
+                None
+            } else {
+                Some((cell, dir))
+            }
+        }).unwrap();
 
-        // Sometimes escape cell can be blocked out from the copied area during
-        // generation. That's why we make it reachable before running generation.
-        carve_path(&mut new_layer, source_coord, &path_to_escape);
+        let entrance = Entrance { source_layer_index, source_coord, back, escape, escape_dir };
+        Ok((entrance, region, exit))
+    }
 
-        generate(
-            &mut new_layer, std::iter::once(escape), region_to_copy.cells(),
-            &mut self.rng
-        );
-        // Run generation again over the entire layer to get rid of blocked out
-        // unreachable regions.
-        generate(
-            &mut new_layer, self.shape.iter().copied(), region_to_copy.cells(),
-            &mut self.rng
-        );
+    /// Copies the surroundings of every `(source_layer_index, source_coord)`
+    /// pair into a new layer, leaving the rest of it to be carved.
+    /// The copied areas must not touch each other.
+    fn prepare_layer(
+        &mut self,
+        sources: &[(usize, (i32, i32))],
+        options: &LayerOptions,
+    ) -> Result<LayerJob, GenerationError> {
+        if options.region.is_some() && sources.len() > 1 {
+            return Err(GenerationError::RegionForSeveralCells { cells: sources.len() });
+        }
+        let mut layer = Layer::from_shape_with_topology(&self.shape, self.topology);
+        let mut entrances = Vec::new();
+        let mut exits = Vec::new();
+        let mut passages = Vec::new();
+        let mut cells = HashSet::new();
+        for &(source_layer_index, source_coord) in sources {
+            let (entrance, region, exit) =
+                self.find_entrance(source_layer_index, source_coord, options)?;
+            if region.cells().iter().chain(region.boundary()).any(|cell| cells.contains(cell)) {
+                return Err(GenerationError::RegionsTouch {
+                    layer: source_layer_index, cell: source_coord
+                });
+            }
+            let source = &self.maze.as_ref().unwrap().maze_layer(source_layer_index).layer;
+            copy_region(source, source_coord, source_layer_index, &mut layer, &region);
+            passages.extend(passages_out(source, &region));
+            cells.extend(region.cells().iter().copied());
+            entrances.push(entrance);
+            exits.push(exit);
+        }
+        let region = Region::with_topology(cells, self.topology);
 
-        let info = traversal::dfs(
-            &new_layer, source_coord, Some(back)
-        );
+        if self.algorithm.is_legacy() {
+            // Legacy layers keep every passage out of the copied areas and
+            // the paths to the escapes, so that the escapes can't be blocked
+            // out, and carving starts from the escapes.
+            for &(cell, dir) in &passages {
+                layer.join(cell, dir);
+            }
+            for entrance in &entrances {
+                let info = &self.maze.as_ref().unwrap().maze_layer(entrance.source_layer_index).info;
+                let path = traversal::get_path_to(entrance.source_coord, entrance.escape, info);
+                carve_path(&mut layer, entrance.source_coord, &path);
+            }
+            return Ok(LayerJob {
+                spawn_points: entrances.iter().map(|entrance| entrance.escape).collect(),
+                entrances,
+                late_passages: Vec::new(),
+                layer, region,
+                params: options.params.clone().unwrap_or_else(|| self.params.clone()),
+                visibility_radius: self.visibility_radius,
+            });
+        }
+
+        // The paths to the escapes leave the copied areas through `exits`.
+        // The rest of the layer is mostly grown from the first of them,
+        // so the new part of the layer lies ahead of the player.
+        let (connecting, late_passages) = split_passages_out(&layer, &region, &exits, &passages)
+            .ok_or(GenerationError::DisconnectedLayer { shape_size: self.shape.len() })?;
+        for &(cell, dir) in &connecting {
+            layer.join(cell, dir);
+        }
+        let mut spawn_points: Vec<_> = connecting.iter().map(|&(cell, dir)| cell + dir).collect();
+        spawn_points.reverse();
 
-        let new_layer_index = maze.add_layer(new_layer, info, source_layer_index);
-        maze.add_transition(source_coord, escape_dir, source_layer_index, new_layer_index);
+        Ok(LayerJob {
+            entrances, spawn_points, late_passages, layer, region,
+            params: options.params.clone().unwrap_or_else(|| self.params.clone()),
+            visibility_radius: self.visibility_radius,
+        })
+    }
+
+    /// Carves the layers of `jobs` and adds them to the maze.
+    ///
+    /// The layers are carved on separate threads. Each of them has its own
+    /// random generator seeded from the builder's one, and the layers are
+    /// added to the maze in the order of `jobs`, so the result doesn't depend
+    /// on scheduling. Legacy algorithms carve the layers one after another
+    /// with the builder's generator instead, so their mazes don't change.
+    ///
+    /// Fails without changing the maze if two transitions would be entered
+    /// through the same cell.
+    fn add_layers(&mut self, jobs: Vec<LayerJob>) -> Result<Vec<usize>, GenerationError> {
+        let maze = self.maze.as_ref().unwrap();
+        let mut entered = HashSet::new();
+        for entrance in jobs.iter().flat_map(|job| &job.entrances) {
+            let layer = entrance.source_layer_index;
+            let cell = entrance.source_coord + entrance.escape_dir;
+            if maze.layer_after_entering(layer, cell) != layer || !entered.insert((layer, cell)) {
+                return Err(GenerationError::TransitionsOverlap { layer, cell });
+            }
+        }
+
+        let shape = &self.shape;
+        let algorithm = self.algorithm;
+        let rng = &mut *self.rng;
+        let carved: Vec<CarvedLayer> = if algorithm.is_legacy() {
+            jobs.into_iter().map(|job| job.carve(shape, algorithm, rng)).collect()
+        } else {
+            let seeds: Vec<u64> = jobs.iter().map(|_| rng.gen()).collect();
+            std::thread::scope(|scope| {
+                let handles: Vec<_> = jobs.into_iter().zip(seeds)
+                    .map(|(job, seed)| scope.spawn(move || {
+                        job.carve(shape, algorithm, &mut SmallRng::seed_from_u64(seed))
+                    }))
+                    .collect();
+                handles.into_iter()
+                    .map(|handle| handle.join().expect("Layer generation panicked"))
+                    .collect()
+            })
+        };
+
+        let maze = self.maze.as_mut().unwrap();
+        Ok(carved.into_iter().map(|carved| {
+            let new_layer_index = maze.add_layer(carved.layer, carved.info);
+            for entrance in &carved.entrances {
+                maze.add_transition(
+                    entrance.source_coord, entrance.escape_dir,
+                    entrance.source_layer_index, new_layer_index
+                );
+            }
+            new_layer_index
+        }).collect())
+    }
+
+    /// Adds a layer with a transition to it at `cell` of the layer
+    /// `src_layer`. The player enters the new layer when going from `cell`
+    /// towards the escape.
+    ///
+    /// Fails if `cell` is the start of the layer or can't be reached, if
+    /// there is no suitable escape, or if some cells of the new layer would
+    /// be unreachable with the region copied.
+    pub fn add_layer_at(
+        &mut self,
+        src_layer: usize,
+        cell: (i32, i32),
+        options: LayerOptions,
+    ) -> Result<usize, GenerationError> {
+        self.add_shared_layer(&[(src_layer, cell)], options)
+    }
 
-        new_layer_index
+    /// Adds a single layer which every `(src_layer, cell)` pair leads to,
+    /// just like `add_layer_at` would do. Different ways through the maze
+    /// meet in the new layer. Depths in the new layer, and so its deepest
+    /// point, are counted from the first pair.
+    ///
+    /// Fails if the areas copied around the cells touch each other, or if
+    /// `options` sets the copied region for several pairs.
+    pub fn add_shared_layer(
+        &mut self,
+        sources: &[(usize, (i32, i32))],
+        options: LayerOptions,
+    ) -> Result<usize, GenerationError> {
+        let job = self.prepare_layer(sources, &options)?;
+        Ok(self.add_layers(vec![job])?[0])
     }
 
     pub fn generate_first_layer(
@@ -159,73 +732,121 @@ impl<'r> MazeBuilder<'r> {
         spawn_points: &[(i32, i32)]
     ) -> usize {
         assert!(!spawn_points.is_empty());
-        let mut layer = Layer::from_shape(&self.shape);
+        let mut layer = Layer::from_shape_with_topology(&self.shape, self.topology);
         for &spawn_point in &spawn_points[1..] {
             layer.treat_as_reachable(spawn_points[0], spawn_point);
         }
-        generate(&mut layer, spawn_points.iter().copied(), &Default::default(), &mut self.rng);
+        self.algorithm.carve(
+            &mut layer, spawn_points, &Default::default(),
+            &self.params, self.rng
+        );
+        braid(
+            &mut layer, self.shape.iter().copied(), &Default::default(),
+            &self.params, self.rng
+        );
 
-        self.maze = Some(Maze::new(layer, spawn_points[0]));
+        let dfs = if self.algorithm.is_legacy() { traversal::legacy_dfs } else { traversal::dfs };
+        let info = dfs(&layer, spawn_points[0], None, self.visibility_radius);
+        self.maze = Some(Maze::with_traversal(
+            layer, info, spawn_points[0], self.visibility_radius
+        ));
 
         0
     }
 
+    fn deepest_leaf_escapable(&mut self, src_layer: usize) -> Result<(i32, i32), GenerationError> {
+        let shape_size = self.shape.len();
+        let info = self.traversal_info(src_layer);
+        let deepest = *info.leaf_escapables.iter().max_by_key(
+            |coord| info.coords[coord].depth
+        ).ok_or(GenerationError::NotEnoughLeafEscapables {
+            layer: src_layer, found: 0, needed: 1, shape_size
+        })?;
+        if info.coords[&deepest].depth == 0 {
+            return Err(GenerationError::DeepestPointAtStart { layer: src_layer, shape_size });
+        }
+        Ok(deepest)
+    }
+
     pub fn add_layer_from_deepest_point(
         &mut self,
         src_layer: usize,
     ) -> Result<usize, GenerationError> {
-        let info = self.traversal_info(src_layer);
-        let deepest = *info.leaf_escapables.iter().max_by_key(
-            |coord| info.coords[&coord].depth
-        ).ok_or(GenerationError{})?;
-        if info.coords[&deepest].depth == 0 {
-            return Err(GenerationError{});
+        let deepest = self.deepest_leaf_escapable(src_layer)?;
+        self.add_layer_at(src_layer, deepest, LayerOptions::default())
+    }
+
+    /// Same as calling `add_layer_from_deepest_point` for each of
+    /// `src_layers` in turn, but the new layers are carved in parallel.
+    pub fn add_layers_from_deepest_points(
+        &mut self,
+        src_layers: &[usize],
+    ) -> Result<Vec<usize>, GenerationError> {
+        if self.algorithm.is_legacy() {
+            // Legacy layers used to be added one by one, so the random
+            // number generator is left where it used to be if one fails.
+            return src_layers.iter()
+                .map(|&src_layer| self.add_layer_from_deepest_point(src_layer))
+                .collect();
         }
+        let sources = src_layers.iter()
+            .map(|&src_layer| Ok((src_layer, self.deepest_leaf_escapable(src_layer)?)))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.add_layers_at(&sources)
+    }
 
-        let new_layer_index = self.add_layer(
-            src_layer,
-            deepest
-        );
+    /// Adds a layer with the default options for every `(src_layer, cell)`
+    /// pair, carving them in parallel.
+    fn add_layers_at(
+        &mut self,
+        sources: &[(usize, (i32, i32))],
+    ) -> Result<Vec<usize>, GenerationError> {
+        let jobs = sources.iter()
+            .map(|&source| self.prepare_layer(&[source], &LayerOptions::default()))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.add_layers(jobs)
+    }
 
-        Ok(new_layer_index)
+    fn check_leaf_escapables(&mut self, src_layer: usize, needed: usize) -> Result<(), GenerationError> {
+        let shape_size = self.shape.len();
+        let found = self.traversal_info(src_layer).leaf_escapables.len();
+        if found < needed {
+            return Err(GenerationError::NotEnoughLeafEscapables {
+                layer: src_layer, found, needed, shape_size
+            });
+        }
+        Ok(())
     }
 
     pub fn fork_to_two_layers(
         &mut self,
         src_layer: usize
     ) -> Result<(usize, usize), GenerationError> {
+        self.check_leaf_escapables(src_layer, 2)?;
         let leaf_escapables = &self.traversal_info(src_layer).leaf_escapables;
-        if leaf_escapables.len() < 2 {
-            return Err(GenerationError{});
-        }
         let first = *leaf_escapables.first().unwrap();
         let last = *leaf_escapables.last().unwrap();
-        Ok((
-            self.add_layer(src_layer, first),
-            self.add_layer(src_layer, last)
-        ))
+        let layers = self.add_layers_at(&[(src_layer, first), (src_layer, last)])?;
+        Ok((layers[0], layers[1]))
     }
 
     pub fn fork_to_three_layers(
         &mut self,
         src_layer: usize
     ) -> Result<(usize, usize, usize), GenerationError> {
+        self.check_leaf_escapables(src_layer, 3)?;
         let info = &self.traversal_info(src_layer);
         let leaf_escapables = &info.leaf_escapables;
 
-        if leaf_escapables.len() < 3 {
-            return Err(GenerationError{});
-        }
         let first = *leaf_escapables.first().unwrap();
         let last = *leaf_escapables.last().unwrap();
         let deepest = *leaf_escapables[1..leaf_escapables.len() - 1].iter().max_by_key(
-            |coord| info.coords[&coord].depth
+            |coord| info.coords[coord].depth
         ).unwrap();
-        Ok((
-            self.add_layer(src_layer, first),
-            self.add_layer(src_layer, deepest),
-            self.add_layer(src_layer, last)
-        ))
+        let layers = self.add_layers_at(&[
+            (src_layer, first), (src_layer, deepest), (src_layer, last)
+        ])?;
+        Ok((layers[0], layers[1], layers[2]))
     }
 
     pub fn set_finish_at_deepest_point(
@@ -240,6 +861,338 @@ impl<'r> MazeBuilder<'r> {
         let maze = self.maze.as_mut().unwrap();
         maze.set_finish((deepest.0, deepest.1, layer_index));
     }
+
+    /// Puts an item at the deepest untouched cell of every layer no other
+    /// layer was added from, so that the player has to visit all of them
+    /// before the finish counts. Returns the number of items placed.
+    pub fn place_items_in_leaf_layers(&mut self) -> usize {
+        let maze = self.maze.as_mut().unwrap();
+        let cells: Vec<_> = maze.leaf_layers().into_iter().filter_map(|layer_index| {
+            let info = &maze.maze_layer(layer_index).info;
+            info.coords.iter()
+                .filter(|&(&(x, y), _)| {
+                    // Cells with transitions are never stood on in this layer.
+                    maze.layer_after_entering(layer_index, (x, y)) == layer_index
+                        && maze.cell_info((x, y, layer_index)) == Some(CellInfo::Untouched)
+                })
+                .max_by_key(|&(&coord, info)| (info.depth, coord))
+                .map(|(&(x, y), _)| (x, y, layer_index))
+        }).collect();
+
+        let mut placed = 0;
+        for cell in cells {
+            // Leaf layers may share the cell.
+            if maze.cell_info(cell) == Some(CellInfo::Untouched) {
+                maze.add_item(cell);
+                placed += 1;
+            }
+        }
+        placed
+    }
+}
+
+#[test]
+fn test_parallel_layers_match_sequential() {
+    use rand::SeedableRng;
+    use crate::geometry_sets::make_circle;
+    use crate::visible_area::DEFAULT_VISIBILITY_RADIUS;
+
+    fn passages(maze: &Maze, index: usize) -> Vec<((i32, i32), Dir)> {
+        let layer = &maze.maze_layer(index).layer;
+        make_circle(15)
+            .flat_map(|c| layer.directions(c).iter().map(move |&dir| (c, dir)))
+            .filter(|&(c, dir)| layer.passable(c, dir))
+            .collect()
+    }
+
+    let build = |parallel: bool| {
+        let mut rng = SmallRng::seed_from_u64(7);
+        let mut builder = MazeBuilder::new(
+            make_circle(15).collect(), Default::default(), DEFAULT_VISIBILITY_RADIUS, &mut rng
+        );
+        let first = builder.generate_first_layer((0, 0));
+        let info = builder.traversal_info(first);
+        let first_leaf = *info.leaf_escapables.first().unwrap();
+        let last_leaf = *info.leaf_escapables.last().unwrap();
+        if parallel {
+            builder.fork_to_two_layers(first).unwrap();
+        } else {
+            builder.add_layer_at(first, first_leaf, LayerOptions::default()).unwrap();
+            builder.add_layer_at(first, last_leaf, LayerOptions::default()).unwrap();
+        }
+        builder.into_maze()
+    };
+
+    let sequential = build(false);
+    for _ in 0..3 {
+        let parallel = build(true);
+        for index in 1..3 {
+            assert_eq!(passages(&sequential, index), passages(&parallel, index));
+        }
+    }
+}
+
+#[test]
+fn test_add_layer_at() {
+    use rand::SeedableRng;
+    use crate::generation::CellParams;
+    use crate::geometry_sets::make_circle;
+    use crate::visible_area::DEFAULT_VISIBILITY_RADIUS;
+
+    let shape: Vec<_> = make_circle(15).collect();
+    let mut rng = SmallRng::seed_from_u64(3);
+    let mut builder = MazeBuilder::new(
+        shape.clone(), Default::default(), DEFAULT_VISIBILITY_RADIUS, &mut rng
+    );
+    let first = builder.generate_first_layer((0, 0));
+    assert_eq!(
+        builder.add_layer_at(first, (0, 0), LayerOptions::default()),
+        Err(GenerationError::TransitionAtStart { layer: first, cell: (0, 0) })
+    );
+
+    // A junction some way from the start.
+    let maze_layer = builder.maze.as_ref().unwrap().maze_layer(first);
+    let (info, layer) = (&maze_layer.info, &maze_layer.layer);
+    let junction = *shape.iter()
+        .filter(|&&c| info.coords[&c].depth > 4)
+        .find(|&&c| layer.directions(c).iter().filter(|&&dir| layer.passable(c, dir)).count() > 2)
+        .unwrap();
+    let region = Region::from(
+        make_circle(3).map(|(x, y)| (x + junction.0, y + junction.1)).collect::<HashSet<_>>()
+    );
+    let outside = *shape.iter()
+        .find(|&&c| info.coords[&c].depth > 0 && !region.cells().contains(&c))
+        .unwrap();
+    let escape_at = |cell, escape| {
+        let options = LayerOptions { region: Some(region.clone()), escape, params: None };
+        builder.find_entrance(first, cell, &options).map(|(entrance, _, _)| entrance.escape)
+    };
+    assert_eq!(
+        escape_at(outside, Escape::Any),
+        Err(GenerationError::CellOutsideRegion { layer: first, cell: outside })
+    );
+
+    // Escapes are chosen among the cells ahead of the junction.
+    let candidates = escape_candidates(layer, info, junction, &region);
+    let depths: Vec<_> = candidates.iter().map(|c| info.coords[c].depth).collect();
+    let escape = |escape| escape_at(junction, escape);
+    let nearest = escape(Escape::Nearest).unwrap();
+    assert_eq!(info.coords[&nearest].depth, *depths.iter().min().unwrap());
+    assert_eq!(escape(Escape::Any), Ok(nearest));
+    let deepest = escape(Escape::Deepest).unwrap();
+    assert_eq!(info.coords[&deepest].depth, *depths.iter().max().unwrap());
+    assert_eq!(escape(Escape::At(deepest)), Ok(deepest));
+    assert_eq!(
+        escape(Escape::At(junction)),
+        Err(GenerationError::NoEscape { layer: first, cell: junction })
+    );
+
+    let options = LayerOptions {
+        region: Some(region.clone()),
+        escape: Escape::Deepest,
+        params: Some(GenerationParams::new(CellParams::default())),
+    };
+    let second = builder.add_layer_at(first, junction, options).unwrap();
+
+    let maze = builder.into_maze();
+    let source = &maze.maze_layer(first).layer;
+    let added = &maze.maze_layer(second).layer;
+    for &c in region.cells() {
+        for &dir in source.directions(c) {
+            if source.has(c) && region.cells().contains(&(c + dir)) {
+                assert_eq!(source.passable(c, dir), added.passable(c, dir));
+            }
+        }
+    }
+    assert!(shape.iter().all(|&c| added.reachable(junction, c)));
+}
+
+#[test]
+fn test_generation_errors() {
+    use rand::SeedableRng;
+    use crate::visible_area::DEFAULT_VISIBILITY_RADIUS;
+
+    // A corridor has no dead ends except for its ends.
+    let shape: Vec<_> = (0..5).map(|x| (x, 0)).collect();
+    let mut rng = SmallRng::seed_from_u64(0);
+    let mut builder = MazeBuilder::new(
+        shape, Default::default(), DEFAULT_VISIBILITY_RADIUS, &mut rng
+    );
+    let first = builder.generate_first_layer((0, 0));
+    let error = builder.fork_to_three_layers(first).unwrap_err();
+    assert!(matches!(
+        error,
+        GenerationError::NotEnoughLeafEscapables { layer: 0, needed: 3, shape_size: 5, .. }
+    ));
+
+    let error = error.in_step("forking");
+    assert!(error.to_string().starts_with("forking: layer 0 of 5 cells"));
+    assert!(std::error::Error::source(&error).is_some());
+}
+
+#[test]
+fn test_overlapping_transitions() {
+    use rand::SeedableRng;
+    use crate::geometry_sets::make_circle;
+    use crate::visible_area::DEFAULT_VISIBILITY_RADIUS;
+
+    let mut rng = SmallRng::seed_from_u64(0);
+    let mut builder = MazeBuilder::new(
+        make_circle(15).collect(), Default::default(), DEFAULT_VISIBILITY_RADIUS, &mut rng
+    );
+    let first = builder.generate_first_layer((0, 0));
+    let second = builder.add_layer_from_deepest_point(first).unwrap();
+    // The deepest point already leads to the second layer.
+    assert!(matches!(
+        builder.add_layer_from_deepest_point(first),
+        Err(GenerationError::TransitionsOverlap { layer: 0, .. })
+    ));
+    // Neither can two new layers be entered through the same cell.
+    let deepest = builder.deepest_leaf_escapable(second).unwrap();
+    assert!(matches!(
+        builder.add_layers_at(&[(second, deepest), (second, deepest)]),
+        Err(GenerationError::TransitionsOverlap { layer: 1, .. })
+    ));
+    // Nothing was added by the failed attempts.
+    assert_eq!(builder.add_layer_from_deepest_point(second).unwrap(), 2);
+}
+
+#[test]
+fn test_visibility_radius() {
+    use rand::SeedableRng;
+    use crate::geometry_sets::make_circle;
+    use crate::visible_area::DEFAULT_VISIBILITY_RADIUS;
+
+    // The whole layer is visible from every cell, so there is no place
+    // for a transition unless the player sees less.
+    let shape: Vec<_> = make_circle(6).collect();
+    let mut rng = SmallRng::seed_from_u64(0);
+    let mut builder = MazeBuilder::new(
+        shape.clone(), Default::default(), DEFAULT_VISIBILITY_RADIUS, &mut rng
+    );
+    let first = builder.generate_first_layer((0, 0));
+    assert!(builder.add_layer_from_deepest_point(first).is_err());
+
+    let mut builder = MazeBuilder::new(shape, Default::default(), 3, &mut rng);
+    let first = builder.generate_first_layer((0, 0));
+    builder.add_layer_from_deepest_point(first).unwrap();
+    assert_eq!(builder.into_maze().visibility_radius(), 3);
+}
+
+#[test]
+fn test_add_shared_layer() {
+    use rand::SeedableRng;
+    use crate::geometry_sets::make_circle;
+    use crate::maze::analysis;
+    use crate::visible_area::DEFAULT_VISIBILITY_RADIUS;
+
+    // The areas copied around the transitions must not touch, so the layers
+    // are large and the transition of the right fork is as far as possible
+    // from the one of the left fork.
+    let shape: Vec<_> = make_circle(25).collect();
+    let (maze, forks, shared) = (0..10).find_map(|seed| {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let mut builder = MazeBuilder::new(
+            shape.clone(), Default::default(), DEFAULT_VISIBILITY_RADIUS, &mut rng
+        );
+        let first = builder.generate_first_layer((0, 0));
+        let (left, right) = builder.fork_to_two_layers(first).ok()?;
+        let left_cell = builder.deepest_leaf_escapable(left).ok()?;
+        let info = builder.traversal_info(right);
+        let right_cell = *info.leaf_escapables.iter()
+            .filter(|&&c| info.coords[&c].depth > 0)
+            .max_by_key(|&&(x, y)| (x - left_cell.0).pow(2) + (y - left_cell.1).pow(2))?;
+        let sources = [(left, left_cell), (right, right_cell)];
+        let options = LayerOptions {
+            region: Some(Region::from(shape.iter().copied().collect::<HashSet<_>>())),
+            ..Default::default()
+        };
+        assert_eq!(
+            builder.add_shared_layer(&sources, options),
+            Err(GenerationError::RegionForSeveralCells { cells: 2 })
+        );
+        let shared = builder.add_shared_layer(&sources, LayerOptions::default()).ok()?;
+        builder.set_finish_at_deepest_point(shared);
+        Some((builder.into_maze(), [left, right], shared))
+    }).expect("no shared layer could be added");
+
+    let layer = &maze.maze_layer(shared).layer;
+    assert!(shape.iter().all(|&c| layer.reachable(shape[0], c)));
+
+    // The finish is reached through one of the forks.
+    let report = analysis::analyze(&maze);
+    assert_eq!(report.solution.map(|solution| solution.layer_switches), Some(2));
+    for &fork in &forks {
+        assert!(report.layers[fork].reachable > 0);
+    }
+}
+
+#[test]
+fn test_added_layers_have_no_unreachable_cells() {
+    use rand::SeedableRng;
+    use crate::generation::{
+        LegacyGrowingTree, RecursiveBacktracker, Prim, Kruskal, Wilson, Eller
+    };
+    use crate::geometry_sets::{
+        make_circle, make_hexagon, make_ring, make_lemniscate, make_hourglass
+    };
+    use crate::visible_area::DEFAULT_VISIBILITY_RADIUS;
+
+    let shapes: Vec<(Vec<(i32, i32)>, Topology)> = vec![
+        (make_circle(14).collect(), Topology::Square),
+        (make_circle(18).collect(), Topology::Triangle),
+        (make_hexagon(16).collect(), Topology::Hex),
+        (make_ring(8, 17).collect(), Topology::Square),
+        (make_lemniscate(20.0, 3).collect(), Topology::Square),
+        (make_hourglass(12).collect(), Topology::Square),
+    ];
+    let algorithms: [&'static dyn CarvingAlgorithm<LazyCellInfo>; 7] = [
+        &GrowingTree(), &LegacyGrowingTree(), &RecursiveBacktracker(),
+        &Prim(), &Kruskal(), &Wilson(), &Eller(),
+    ];
+    // Every shape is tried with two of the algorithms to keep the test fast.
+    for (i, (shape, topology)) in shapes.into_iter().enumerate() {
+        for &algorithm in algorithms.iter().cycle().skip(2 * i).take(2) {
+            let mut rng = SmallRng::seed_from_u64(0);
+            let mut builder = MazeBuilder::new(
+                shape.clone(), Default::default(), DEFAULT_VISIBILITY_RADIUS, &mut rng
+            );
+            builder.set_topology(topology);
+            builder.set_carving_algorithm(algorithm);
+            let mut last = builder.generate_first_layer(shape[shape.len() / 2]);
+            while let Ok(layer) = builder.add_layer_from_deepest_point(last) {
+                last = layer;
+                if last == 2 {
+                    break;
+                }
+            }
+            assert!(last > 0, "no layer was added to {:?} shape", topology);
+
+            let maze = builder.into_maze();
+            for index in 0..=last {
+                let layer = &maze.maze_layer(index).layer;
+                assert!(shape.iter().all(|&c| layer.reachable(shape[0], c)));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_large_layer() {
+    use rand::SeedableRng;
+    use itertools::Itertools;
+    use crate::visible_area::DEFAULT_VISIBILITY_RADIUS;
+
+    // Paths in layers of this size are far too long for recursive traversal.
+    let shape: Vec<_> = (0..500).cartesian_product(0..500).collect();
+    let mut rng = SmallRng::seed_from_u64(0);
+    let mut builder = MazeBuilder::new(
+        shape, Default::default(), DEFAULT_VISIBILITY_RADIUS, &mut rng
+    );
+    let first = builder.generate_first_layer((250, 250));
+    let second = builder.add_layer_from_deepest_point(first).unwrap();
+    let maze = builder.into_maze();
+    assert_eq!(maze.maze_layer(second).layer.cells().count(), 500 * 500);
 }
 
 #[cfg(feature = "bench")]
@@ -249,14 +1202,27 @@ extern crate test;
 
 #[bench]
 fn bench_add_layer(b: &mut test::Bencher) {
-    use crate::build::{MazeBuilder, make_circle};
+    use rand::SeedableRng;
+    use rand::rngs::SmallRng;
+    use crate::build::MazeBuilder;
+    use crate::geometry_sets::make_circle;
+    use crate::visible_area::DEFAULT_VISIBILITY_RADIUS;
 
     let shape = make_circle(15).collect();
-    let mut builder = MazeBuilder::new(0, shape);
-    builder.generate_first_layer((0, 0));
+    let mut rng = SmallRng::seed_from_u64(0);
+    let mut builder = MazeBuilder::new(
+        shape, Default::default(), DEFAULT_VISIBILITY_RADIUS, &mut rng
+    );
 
+    // Every layer gets a single transition from its deepest point, so layers
+    // are added one after another. Once in a while there is no place for
+    // a transition, and a new maze is started.
+    let mut last = builder.generate_first_layer((0, 0));
     b.iter(|| {
-        builder.add_layer_from_deepest_point(0).unwrap();
+        last = match builder.add_layer_from_deepest_point(last) {
+            Ok(layer) => layer,
+            Err(_) => builder.generate_first_layer((0, 0)),
+        };
     });
 }
 
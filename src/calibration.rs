@@ -0,0 +1,150 @@
+use rand::rngs::SmallRng;
+
+use crate::build::GenerationError;
+use crate::generation::GenerationParams;
+use crate::levels::LevelGenerator;
+use crate::maze::{Maze, difficulty};
+
+/// The number of mazes generated for measuring the difficulty of a stage.
+const SAMPLES: u64 = 4;
+
+/// Generators are not asked for stages beyond this one, as mazes get too
+/// large to be generated quickly.
+const MAX_GENERATOR_STAGE: u32 = 40;
+
+/// Chances for dead ends to be joined tried on every generator stage.
+/// Loops shorten the way to the finish but get wall followers lost, so
+/// the difficulty may change either way.
+const BRAIDS: [f64; 6] = [0.0, 0.1, 0.2, 0.3, 0.4, 0.5];
+
+/// Difficulty of the stages growing by the same factor every stage.
+#[derive(Debug, Clone, Copy)]
+pub struct TargetCurve {
+    pub first: f32,
+    /// How much harder every stage is than the previous one, `0.2` is 20%.
+    pub growth: f32,
+}
+
+impl TargetCurve {
+    pub fn at(&self, stage: u32) -> f32 {
+        self.first * (1.0 + self.growth).powi(stage as i32)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CalibratedStage {
+    /// The stage to pass to the generator.
+    pub generator_stage: u32,
+    /// The chance for dead ends to be joined to set in the parameters
+    /// of the generator stage.
+    pub braid: f64,
+    pub difficulty: f32,
+    pub target: f32,
+}
+
+/// Generates the mazes of a level with dead ends joined by the given chance.
+struct Braided<'a> {
+    generator: &'a dyn LevelGenerator,
+    braid: f64,
+}
+
+impl LevelGenerator for Braided<'_> {
+    fn id(&self) -> &'static str { self.generator.id() }
+    fn recommended_length(&self) -> u32 { self.generator.recommended_length() }
+    fn intro_text(&self) -> &'static str { self.generator.intro_text() }
+
+    fn generation_params(&self, stage: u32) -> GenerationParams {
+        let mut params = self.generator.generation_params(stage);
+        params.set_braid(self.braid);
+        params
+    }
+
+    fn visibility_radius(&self, stage: u32) -> i32 {
+        self.generator.visibility_radius(stage)
+    }
+
+    fn try_generate(
+        &self, stage: u32, params: GenerationParams, rng: &mut SmallRng
+    ) -> Result<Maze, GenerationError> {
+        self.generator.try_generate(stage, params, rng)
+    }
+}
+
+/// Average difficulty of the mazes of the stage with dead ends joined by
+/// the `braid` chance. Returns `None` if they can't be generated or
+/// estimated, which happens when mazes have no finish.
+pub fn measure(generator: &dyn LevelGenerator, stage: u32, braid: f64) -> Option<f32> {
+    let generator = Braided { generator, braid };
+    let mut total = 0.0;
+    for seed in 0..SAMPLES {
+        total += difficulty::estimate(&generator.generate(stage, seed).ok()?)?.total();
+    }
+    Some(total / SAMPLES as f32)
+}
+
+/// Picks generator stages and parameters for `stages` consecutive stages,
+/// so that each of them is at least as hard as `curve` requires and harder
+/// than the previous one. Of the chances for dead ends to be joined which
+/// make a generator stage fit, the one closest to the curve is picked.
+/// `measure` tells the difficulty of a generator stage with the given
+/// chance for dead ends to be joined.
+///
+/// Stops early if the curve can't be followed any further.
+pub fn calibrate(
+    stages: u32,
+    curve: TargetCurve,
+    mut measure: impl FnMut(u32, f64) -> Option<f32>
+) -> Vec<CalibratedStage> {
+    let mut result: Vec<CalibratedStage> = Vec::new();
+    let mut generator_stage = 0;
+    for stage in 0..stages {
+        let target = curve.at(stage);
+        loop {
+            if generator_stage > MAX_GENERATOR_STAGE {
+                return result;
+            }
+            let mut best: Option<CalibratedStage> = None;
+            for &braid in &BRAIDS {
+                let difficulty = match measure(generator_stage, braid) {
+                    Some(difficulty) => difficulty,
+                    // The generator can't go any further.
+                    None if braid == 0.0 => return result,
+                    None => continue,
+                };
+                let fits = difficulty >= target
+                    && result.last().is_none_or(|prev| difficulty > prev.difficulty);
+                if fits && best.is_none_or(|best| difficulty < best.difficulty) {
+                    best = Some(CalibratedStage { generator_stage, braid, difficulty, target });
+                }
+            }
+            generator_stage += 1;
+
+            if let Some(calibrated) = best {
+                result.push(calibrated);
+                break;
+            }
+        }
+    }
+    result
+}
+
+#[test]
+fn test_calibrate() {
+    let curve = TargetCurve { first: 100.0, growth: 0.5 };
+    // Every generator stage adds 20 to the difficulty, except for
+    // the third one, which is easier than the second. Every 0.1 of braid
+    // takes 10 away.
+    let measure = |stage, braid: f64| Some(match stage {
+        2 => 110.0,
+        stage => 100.0 + 20.0 * stage as f32,
+    } - 100.0 * braid as f32);
+    let stages: Vec<_> = calibrate(4, curve, measure).iter()
+        .map(|calibrated| (calibrated.generator_stage, calibrated.braid))
+        .collect();
+    // The targets are 100, 150, 225 and 337.5.
+    assert_eq!(stages, vec![(0, 0.0), (3, 0.1), (7, 0.1), (12, 0.0)]);
+
+    let stages = calibrate(4, curve, |stage, _| if stage < 5 { Some(200.0) } else { None });
+    assert_eq!(stages.len(), 1);
+}
+
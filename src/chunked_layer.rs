@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use itertools::Itertools;
+use rand::SeedableRng;
+use rand::rngs::SmallRng;
+
+use crate::layer::Layer;
+use crate::geometry::Dir;
+use crate::generation::generate;
+
+pub const CHUNK_SIZE: i32 = 64;
+/// Runs of chunks joined horizontally never cross the borders of groups
+/// of this many chunks, so that they can be found by looking around locally.
+const RUN_GROUP_SIZE: i32 = 4;
+
+const SALT_CARVING: i64 = 1;
+const SALT_RUN_END: i64 = 2;
+const SALT_RUN_EXIT: i64 = 3;
+const SALT_SEAM_RIGHT: i64 = 4;
+const SALT_SEAM_DOWN: i64 = 5;
+
+
+fn splitmix(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Hash which doesn't depend on the platform or the version of the compiler.
+fn hash(values: &[i64]) -> u64 {
+    values.iter().fold(0, |hash, &value| splitmix(hash ^ value as u64))
+}
+
+
+/// Square layer without bounds which is carved in chunks of
+/// `CHUNK_SIZE`x`CHUNK_SIZE` cells on demand.
+///
+/// The contents of every chunk are a pure function of the seed and the
+/// coordinates of the chunk, so chunks can be dropped and carved again.
+///
+/// Passages of all the chunks form a single spanning tree. Every chunk is
+/// a tree itself, and chunks are joined by single passages along the edges
+/// of a spanning tree of the grid of chunks. That tree has a horizontal
+/// spine in the row of chunks with `y == 0`, and the other rows are split
+/// into horizontal runs each of which has a single passage towards the spine.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct ChunkedLayer {
+    seed: u64,
+    /// Chunks are generated from the seed only, so they aren't serialized.
+    #[serde(skip)]
+    chunks: HashMap<(i32, i32), Layer<()>>,
+}
+
+impl ChunkedLayer {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            chunks: HashMap::new(),
+        }
+    }
+
+    pub fn chunk_of((x, y): (i32, i32)) -> (i32, i32) {
+        (x.div_euclid(CHUNK_SIZE), y.div_euclid(CHUNK_SIZE))
+    }
+
+    fn chunk_cells((cx, cy): (i32, i32)) -> impl Iterator<Item=(i32, i32)> {
+        (cx * CHUNK_SIZE..(cx + 1) * CHUNK_SIZE).cartesian_product(cy * CHUNK_SIZE..(cy + 1) * CHUNK_SIZE)
+    }
+
+    fn window_chunks((cx, cy): (i32, i32)) -> Vec<(i32, i32)> {
+        (cx - 1..=cx + 1).cartesian_product(cy - 1..=cy + 1).collect()
+    }
+
+    /// Cells covered by `window`.
+    pub fn window_cells(center_chunk: (i32, i32)) -> impl Iterator<Item=(i32, i32)> {
+        Self::window_chunks(center_chunk).into_iter().flat_map(Self::chunk_cells)
+    }
+
+    fn hash(&self, (cx, cy): (i32, i32), salt: i64) -> u64 {
+        hash(&[self.seed as i64, i64::from(cx), i64::from(cy), salt])
+    }
+
+    fn run_ends_at(&self, chunk: (i32, i32)) -> bool {
+        chunk.0.rem_euclid(RUN_GROUP_SIZE) == RUN_GROUP_SIZE - 1
+            || self.hash(chunk, SALT_RUN_END).is_multiple_of(2)
+    }
+
+    /// Tells whether chunks `chunk` and `chunk + dir` are joined.
+    /// `dir` is either `RIGHT` or `DOWN`.
+    fn chunks_joined(&self, (cx, cy): (i32, i32), dir: Dir) -> bool {
+        match dir {
+            Dir::RIGHT => cy == 0 || !self.run_ends_at((cx, cy)),
+            Dir::DOWN => {
+                // Only one of the rows has a passage to the other one,
+                // and it is the one farther from the spine.
+                let row = if cy + 1 > 0 { cy + 1 } else { cy };
+                let mut first = cx;
+                while first.rem_euclid(RUN_GROUP_SIZE) != 0 && !self.run_ends_at((first - 1, row)) {
+                    first -= 1;
+                }
+                let mut last = cx;
+                while !self.run_ends_at((last, row)) {
+                    last += 1;
+                }
+                let exit = first + (self.hash((first, row), SALT_RUN_EXIT) % (last - first + 1) as u64) as i32;
+                exit == cx
+            },
+            _ => panic!("Chunks are only joined right or down"),
+        }
+    }
+
+    /// The cell of `chunk` which is joined with the neighbouring chunk in `dir`.
+    fn seam(&self, chunk: (i32, i32), dir: Dir) -> (i32, i32) {
+        let (salt, last) = match dir {
+            Dir::RIGHT => (SALT_SEAM_RIGHT, (CHUNK_SIZE - 1, 0)),
+            Dir::DOWN => (SALT_SEAM_DOWN, (0, CHUNK_SIZE - 1)),
+            _ => panic!("Chunks are only joined right or down"),
+        };
+        let offset = (self.hash(chunk, salt) % CHUNK_SIZE as u64) as i32;
+        let (dx, dy) = if dir == Dir::RIGHT { (last.0, offset) } else { (offset, last.1) };
+        (chunk.0 * CHUNK_SIZE + dx, chunk.1 * CHUNK_SIZE + dy)
+    }
+
+    fn carve_chunk(&self, chunk: (i32, i32)) -> Layer<()> {
+        let shape: Vec<_> = Self::chunk_cells(chunk).collect();
+        let mut layer = Layer::from_shape(&shape);
+        let center = (
+            chunk.0 * CHUNK_SIZE + CHUNK_SIZE / 2,
+            chunk.1 * CHUNK_SIZE + CHUNK_SIZE / 2
+        );
+        let mut rng = SmallRng::seed_from_u64(self.hash(chunk, SALT_CARVING));
+        generate(
+            &mut layer, std::iter::once(center), &Default::default(),
+            &Default::default(), &mut rng
+        );
+        layer
+    }
+
+    /// Builds a regular layer out of `chunks`, carving the missing ones.
+    fn materialize<I: Default>(&mut self, chunks: &[(i32, i32)]) -> Layer<I> {
+        for &chunk in chunks {
+            if !self.chunks.contains_key(&chunk) {
+                let layer = self.carve_chunk(chunk);
+                self.chunks.insert(chunk, layer);
+            }
+        }
+
+        let shape: Vec<_> = chunks.iter().copied().flat_map(Self::chunk_cells).collect();
+        let mut result = Layer::from_shape(&shape);
+        for &chunk in chunks {
+            let layer = &self.chunks[&chunk];
+            for cell in Self::chunk_cells(chunk) {
+                for &dir in &[Dir::RIGHT, Dir::DOWN] {
+                    if layer.passable(cell, dir) {
+                        result.join(cell, dir);
+                    }
+                }
+            }
+            for &dir in &[Dir::RIGHT, Dir::DOWN] {
+                let neighbour = chunk + dir;
+                if chunks.contains(&neighbour) && self.chunks_joined(chunk, dir) {
+                    result.join(self.seam(chunk, dir), dir);
+                }
+            }
+        }
+        result
+    }
+
+    /// Builds a regular layer out of 3x3 chunks around `center_chunk`.
+    /// Chunks outside of the window are dropped.
+    pub fn window<I: Default>(&mut self, center_chunk: (i32, i32)) -> Layer<I> {
+        let chunks = Self::window_chunks(center_chunk);
+        let result = self.materialize(&chunks);
+        self.chunks.retain(|chunk, _| chunks.contains(chunk));
+        result
+    }
+}
+
+
+#[test]
+fn test_chunks_are_pure() {
+    let window = |chunked: &mut ChunkedLayer| {
+        let layer = chunked.window::<()>((0, 0));
+        ChunkedLayer::window_cells((0, 0)).map(
+            |cell| (layer.passable(cell, Dir::RIGHT), layer.passable(cell, Dir::DOWN))
+        ).collect::<Vec<_>>()
+    };
+
+    let mut first = ChunkedLayer::new(42);
+    let mut second = ChunkedLayer::new(42);
+    // Different history must not change anything.
+    second.window::<()>((5, -3));
+    assert!(window(&mut first) == window(&mut second));
+
+    let mut third = ChunkedLayer::new(43);
+    assert!(window(&mut first) != window(&mut third));
+}
+
+#[test]
+fn test_chunks_form_spanning_tree() {
+    // Runs never leave their groups and the spine is in the middle,
+    // so these chunks are connected without any chunks outside.
+    let chunks: Vec<_> = (0..RUN_GROUP_SIZE).cartesian_product(-2..=2).collect();
+    let mut chunked = ChunkedLayer::new(7);
+    let layer = chunked.materialize::<()>(&chunks);
+
+    let cells: Vec<_> = chunks.iter().copied().flat_map(ChunkedLayer::chunk_cells).collect();
+    let mut passages = 0;
+    for &cell in &cells {
+        assert!(layer.reachable(cells[0], cell));
+        passages += [Dir::RIGHT, Dir::DOWN].iter().filter(|&&dir| layer.passable(cell, dir)).count();
+    }
+    assert_eq!(passages, cells.len() - 1);
+}
@@ -1,20 +1,194 @@
 extern crate rand;
 
-use std::collections::HashSet;
+use std::collections::{HashSet, HashMap, VecDeque, BTreeMap};
 use crate::layer::Layer;
-use crate::geometry::{Dir, DIRECTIONS};
-use rand::Rng;
+use crate::geometry::{Dir, Topology};
+use rand::{Rng, RngCore};
 use rand::seq::SliceRandom;
 
 const CHANCE_TO_BE_NEXT: f64 = 0.07;
 
+
+/// Parameters shaping the texture of a maze around a single cell.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CellParams {
+    /// Chance for every cell in the queue to be expanded next.
+    /// The higher it is, the closer to the ends of corridors new branches appear.
+    pub branching: f64,
+    /// Additional relative weight of carving in the same direction as
+    /// the previous step. `0.0` means no preference.
+    pub straightness: f64,
+    /// Relative weight of horizontal moves compared to vertical ones.
+    pub horizontal_weight: f64,
+    /// Chance for a dead end to be joined with one of its neighbours
+    /// after carving, creating a loop.
+    pub braid: f64,
+}
+
+impl Default for CellParams {
+    fn default() -> Self {
+        Self {
+            branching: CHANCE_TO_BE_NEXT,
+            straightness: 0.0,
+            horizontal_weight: 1.0,
+            braid: 0.0,
+        }
+    }
+}
+
+impl CellParams {
+    fn is_valid(&self) -> bool {
+        0.0 < self.branching && self.branching <= 1.0
+            && self.straightness >= 0.0
+            && self.horizontal_weight > 0.0
+            && (0.0..=1.0).contains(&self.braid)
+    }
+
+    /// Unbiased parameters make every possible move equally probable.
+    /// The random number generator is then used exactly the same way
+    /// as before biases were introduced, so existing mazes don't change.
+    fn is_unbiased(&self) -> bool {
+        self.straightness == 0.0 && self.horizontal_weight == 1.0
+    }
+
+    fn weight(&self, dir: Dir, previous: Option<Dir>) -> f64 {
+        let mut weight = match dir {
+            Dir::LEFT | Dir::RIGHT => self.horizontal_weight,
+            Dir::UP | Dir::DOWN | Dir::UP_RIGHT | Dir::DOWN_LEFT => 1.0,
+        };
+        if previous == Some(dir) {
+            weight *= 1.0 + self.straightness;
+        }
+        weight
+    }
+
+    fn max_weight(&self) -> f64 {
+        self.horizontal_weight.max(1.0) * (1.0 + self.straightness)
+    }
+}
+
+
+/// Parameters of maze generation which can differ from cell to cell.
+#[derive(Debug, Clone, Default)]
+pub struct GenerationParams {
+    base: CellParams,
+    overrides: HashMap<(i32, i32), CellParams>,
+}
+
+impl GenerationParams {
+    pub fn new(base: CellParams) -> Self {
+        assert!(base.is_valid(), "Invalid generation parameters: {:?}", base);
+        Self {
+            base,
+            overrides: HashMap::new(),
+        }
+    }
+
+    /// Makes generation around `cells` use `params` instead of the base ones.
+    pub fn override_cells(
+        &mut self,
+        cells: impl IntoIterator<Item=(i32, i32)>,
+        params: CellParams
+    ) {
+        assert!(params.is_valid(), "Invalid generation parameters: {:?}", params);
+        for cell in cells {
+            self.overrides.insert(cell, params);
+        }
+    }
+
+    /// Makes dead ends of every cell be joined with the given chance.
+    pub fn set_braid(&mut self, braid: f64) {
+        for params in std::iter::once(&mut self.base).chain(self.overrides.values_mut()) {
+            params.braid = braid;
+            assert!(params.is_valid(), "Invalid generation parameters: {:?}", params);
+        }
+    }
+
+    /// Parameters of the cells which aren't overridden.
+    pub fn base(&self) -> &CellParams {
+        &self.base
+    }
+
+    pub fn at(&self, cell: (i32, i32)) -> &CellParams {
+        self.overrides.get(&cell).unwrap_or(&self.base)
+    }
+}
+
+
+/// A way of carving passages in a layer.
+///
+/// Every algorithm follows the same contract: cells are carved starting from
+/// `spawn_points`, passages never lead into `blocked_cells` (though blocked
+/// spawn points may be expanded from) and no loops are created, so the carved
+/// part of the layer stays a tree.
+///
+/// Each algorithm takes into account only those `params` which make sense for it.
+pub trait CarvingAlgorithm<I: Default>: Send + Sync {
+    fn carve(
+        &self,
+        layer: &mut Layer<I>,
+        spawn_points: &[(i32, i32)],
+        blocked_cells: &HashSet<(i32, i32)>,
+        params: &GenerationParams,
+        rng: &mut dyn RngCore
+    );
+
+    /// Legacy algorithms reproduce the mazes of earlier versions, so layers
+    /// carved by them are built the way they used to be: one after another
+    /// from the same random number generator, with the escapes found by
+    /// `traversal::legacy_dfs`.
+    fn is_legacy(&self) -> bool {
+        false
+    }
+}
+
+/// Picks a random cell near the end of the queue on each step.
+/// Produces long winding corridors with occasional branches.
+///
+/// Uses all of the parameters.
+pub struct GrowingTree();
+
+/// The original implementation of `GrowingTree` which takes quadratic time
+/// in the worst case. Produces the same mazes as before `GrowingTree` was
+/// rewritten, bit for bit, so the levels relying on it don't change.
+///
+/// Uses all of the parameters.
+pub struct LegacyGrowingTree();
+
+/// Always expands the most recently carved cell. Produces very long corridors
+/// and few dead ends.
+///
+/// Uses straightness and horizontal weight.
+pub struct RecursiveBacktracker();
+
+/// Randomized Prim's algorithm. Produces lots of short dead ends.
+///
+/// Uses straightness and horizontal weight.
+pub struct Prim();
+
+/// Randomized Kruskal's algorithm. Joins random walls between disjoint parts.
+///
+/// Doesn't use any parameters.
+pub struct Kruskal();
+
+/// Wilson's algorithm. Produces uniform spanning trees.
+///
+/// Doesn't use any parameters since they would break uniformity.
+pub struct Wilson();
+
+/// Eller's algorithm, carving the layer row by row.
+///
+/// Uses horizontal weight.
+pub struct Eller();
+
+
 fn possible_moves<I: Default>(layer: &Layer<I>, from: (i32, i32), blocked_cells: &HashSet<(i32, i32)>) -> Vec<Dir> {
     if !layer.has(from) {
         return vec![];
     }
 
     let mut result= vec![];
-    for &dir in &DIRECTIONS {
+    for &dir in layer.directions(from) {
         let to = from + dir;
         if layer.has(to)
             && !blocked_cells.contains(&to)
@@ -28,37 +202,162 @@ fn possible_moves<I: Default>(layer: &Layer<I>, from: (i32, i32), blocked_cells:
 
 fn expand_randomly<R, I: Default>(
     layer: &mut Layer<I>, from: (i32, i32), blocked_cells: &HashSet<(i32, i32)>,
+    params: &GenerationParams, previous: Option<Dir>,
     rng: &mut R
 ) -> Option<Dir>
     where R: Rng + ?Sized
 {
-    let moves = possible_moves(&layer, from, blocked_cells);
-    moves.as_slice().choose(rng).map(|&dir| {
+    let moves = possible_moves(layer, from, blocked_cells);
+    let params = params.at(from);
+    let chosen = if params.is_unbiased() {
+        moves.as_slice().choose(rng)
+    } else {
+        moves.as_slice().choose_weighted(rng, |&dir| params.weight(dir, previous)).ok()
+    };
+    chosen.map(|&dir| {
         layer.join(from, dir);
         dir
     })
 }
 
-// There is probably space for optimization here.
-pub fn generate<R: Rng + ?Sized, I: Default>(
+/// Cells which may be carved by algorithms which don't grow from spawn points
+/// directly. These are spawn points themselves and all non-blocked cells
+/// which can be reached from them without passing through blocked cells.
+///
+/// Cells are returned in the order of discovery to keep generation
+/// deterministic.
+struct CarvableRegion<'b> {
+    order: Vec<(i32, i32)>,
+    cells: HashSet<(i32, i32)>,
+    blocked_cells: &'b HashSet<(i32, i32)>,
+    topology: Topology,
+}
+
+impl<'b> CarvableRegion<'b> {
+    fn new<I: Default>(
+        layer: &Layer<I>,
+        spawn_points: impl Iterator<Item=(i32, i32)>,
+        blocked_cells: &'b HashSet<(i32, i32)>
+    ) -> Self {
+        let mut result = Self {
+            order: Vec::new(),
+            cells: HashSet::new(),
+            blocked_cells,
+            topology: layer.topology(),
+        };
+        let mut queue = VecDeque::new();
+        for spawn_point in spawn_points {
+            if layer.has(spawn_point) && result.cells.insert(spawn_point) {
+                result.order.push(spawn_point);
+                queue.push_back(spawn_point);
+            }
+        }
+        while let Some(cell) = queue.pop_front() {
+            for &dir in layer.directions(cell) {
+                let to = cell + dir;
+                if layer.has(to)
+                    && !blocked_cells.contains(&to)
+                    && result.cells.insert(to)
+                {
+                    result.order.push(to);
+                    queue.push_back(to);
+                }
+            }
+        }
+        result
+    }
+
+    fn allows(&self, from: (i32, i32), dir: Dir) -> bool {
+        let to = from + dir;
+        self.topology.has_direction(from, dir)
+            && self.cells.contains(&from) && self.cells.contains(&to)
+            && !(self.blocked_cells.contains(&from) && self.blocked_cells.contains(&to))
+    }
+
+    fn moves(&self, from: (i32, i32)) -> Vec<Dir> {
+        self.topology.directions(from).iter().copied().filter(|&dir| self.allows(from, dir)).collect()
+    }
+
+    /// Joins all the remaining walls between disjoint parts of the region
+    /// in a random order.
+    fn connect_remaining<R, I: Default>(&self, layer: &mut Layer<I>, rng: &mut R)
+        where R: Rng + ?Sized
+    {
+        let topology = self.topology;
+        let mut walls: Vec<((i32, i32), Dir)> = self.order.iter().flat_map(
+            |&cell| topology.forward_directions(cell).map(move |dir| (cell, dir))
+        ).filter(|&(cell, dir)| self.allows(cell, dir)).collect();
+        walls.shuffle(rng);
+        for (cell, dir) in walls {
+            if !layer.reachable(cell, cell + dir) {
+                layer.join(cell, dir);
+            }
+        }
+    }
+}
+
+
+/// Every cell is added to and removed from the queue once, and picking a cell
+/// from the end takes `1 / branching` steps on average, so carving takes
+/// linear time.
+///
+/// The texture is the same as with `grow_tree_legacy`, but the random number
+/// generator is used differently.
+fn grow_tree<R: Rng + ?Sized, I: Default>(
     layer: &mut Layer<I>,
     spawn_points: impl Iterator<Item=(i32, i32)>,
     blocked_cells: &HashSet<(i32, i32)>,
+    params: &GenerationParams,
     rng: &mut R
 ) {
-    let mut queue: Vec<(i32, i32)> = spawn_points.collect();
+    // Cells are stored along with the direction they were carved in.
+    // Only cells which may still have possible moves are kept.
+    let mut queue: Vec<((i32, i32), Option<Dir>)> = spawn_points.map(
+        |cell| (cell, None)
+    ).collect();
 
     while !queue.is_empty() {
-        while queue.iter().last().map_or(false,
-            |cell| possible_moves(&layer, *cell, blocked_cells).is_empty()
+        let chosen = queue.iter().rposition(
+            |&(cell, _)| rng.gen_bool(params.at(cell).branching)
+        );
+        let index = match chosen {
+            Some(index) => index,
+            None => continue,
+        };
+        let (cell, previous) = queue[index];
+        match expand_randomly(layer, cell, blocked_cells, params, previous, rng) {
+            Some(dir) => queue.push((cell + dir, Some(dir))),
+            // The cell is close to the end, so removal is cheap.
+            None => { queue.remove(index); },
+        }
+    }
+}
+
+fn grow_tree_legacy<R: Rng + ?Sized, I: Default>(
+    layer: &mut Layer<I>,
+    spawn_points: impl Iterator<Item=(i32, i32)>,
+    blocked_cells: &HashSet<(i32, i32)>,
+    params: &GenerationParams,
+    rng: &mut R
+) {
+    // Cells are stored along with the direction they were carved in.
+    let mut queue: Vec<((i32, i32), Option<Dir>)> = spawn_points.map(
+        |cell| (cell, None)
+    ).collect();
+
+    while !queue.is_empty() {
+        while queue.iter().last().is_some_and(
+            |(cell, _)| possible_moves(layer, *cell, blocked_cells).is_empty()
         ) {
             queue.pop();
         }
-        let mut new_cell: Option<(i32, i32)> = None;
-        for cell in queue.iter().rev() {
-            if rng.gen_bool(CHANCE_TO_BE_NEXT) {
-                if let Some(dir) = expand_randomly(layer, *cell, blocked_cells, rng) {
-                    new_cell = Some(*cell + dir);
+        let mut new_cell: Option<((i32, i32), Option<Dir>)> = None;
+        for &(cell, previous) in queue.iter().rev() {
+            if rng.gen_bool(params.at(cell).branching) {
+                if let Some(dir) = expand_randomly(
+                    layer, cell, blocked_cells, params, previous, rng
+                ) {
+                    new_cell = Some((cell + dir, Some(dir)));
                 }
                 break;
             }
@@ -69,6 +368,264 @@ pub fn generate<R: Rng + ?Sized, I: Default>(
     }
 }
 
+/// Removes some of the dead ends among `cells` by joining them with
+/// their neighbours. Prefers joining two dead ends with each other.
+///
+/// This introduces loops, so the layer is no longer a tree afterwards.
+pub fn braid<R: Rng + ?Sized, I: Default>(
+    layer: &mut Layer<I>,
+    cells: impl Iterator<Item=(i32, i32)>,
+    blocked_cells: &HashSet<(i32, i32)>,
+    params: &GenerationParams,
+    rng: &mut R
+) {
+    let is_dead_end = |layer: &Layer<I>, cell: (i32, i32)| {
+        layer.directions(cell).iter().filter(|&&dir| layer.passable(cell, dir)).count() == 1
+    };
+
+    for cell in cells {
+        let chance = params.at(cell).braid;
+        if chance == 0.0
+            || blocked_cells.contains(&cell)
+            || !is_dead_end(layer, cell)
+            || !rng.gen_bool(chance)
+        {
+            continue;
+        }
+        let moves: Vec<Dir> = layer.directions(cell).iter().copied().filter(|&dir| {
+            let to = cell + dir;
+            layer.has(to) && !blocked_cells.contains(&to) && !layer.passable(cell, dir)
+        }).collect();
+        let to_dead_ends: Vec<Dir> = moves.iter().copied().filter(
+            |&dir| is_dead_end(layer, cell + dir)
+        ).collect();
+
+        let candidates = if to_dead_ends.is_empty() { moves } else { to_dead_ends };
+        if let Some(&dir) = candidates.choose(rng) {
+            layer.join(cell, dir);
+        }
+    }
+}
+
+/// Carves the layer using `GrowingTree`.
+pub fn generate<R: Rng + ?Sized, I: Default>(
+    layer: &mut Layer<I>,
+    spawn_points: impl Iterator<Item=(i32, i32)>,
+    blocked_cells: &HashSet<(i32, i32)>,
+    params: &GenerationParams,
+    rng: &mut R
+) {
+    grow_tree(layer, spawn_points, blocked_cells, params, rng);
+}
+
+
+impl<I: Default> CarvingAlgorithm<I> for GrowingTree {
+    fn carve(
+        &self,
+        layer: &mut Layer<I>,
+        spawn_points: &[(i32, i32)],
+        blocked_cells: &HashSet<(i32, i32)>,
+        params: &GenerationParams,
+        rng: &mut dyn RngCore
+    ) {
+        grow_tree(layer, spawn_points.iter().copied(), blocked_cells, params, rng);
+    }
+}
+
+impl<I: Default> CarvingAlgorithm<I> for LegacyGrowingTree {
+    fn carve(
+        &self,
+        layer: &mut Layer<I>,
+        spawn_points: &[(i32, i32)],
+        blocked_cells: &HashSet<(i32, i32)>,
+        params: &GenerationParams,
+        rng: &mut dyn RngCore
+    ) {
+        grow_tree_legacy(layer, spawn_points.iter().copied(), blocked_cells, params, rng);
+    }
+
+    fn is_legacy(&self) -> bool {
+        true
+    }
+}
+
+impl<I: Default> CarvingAlgorithm<I> for RecursiveBacktracker {
+    fn carve(
+        &self,
+        layer: &mut Layer<I>,
+        spawn_points: &[(i32, i32)],
+        blocked_cells: &HashSet<(i32, i32)>,
+        params: &GenerationParams,
+        rng: &mut dyn RngCore
+    ) {
+        let mut stack: Vec<((i32, i32), Option<Dir>)> = spawn_points.iter().map(
+            |&cell| (cell, None)
+        ).collect();
+        while let Some(&(cell, previous)) = stack.last() {
+            match expand_randomly(layer, cell, blocked_cells, params, previous, rng) {
+                Some(dir) => stack.push((cell + dir, Some(dir))),
+                None => { stack.pop(); },
+            }
+        }
+    }
+}
+
+impl<I: Default> CarvingAlgorithm<I> for Prim {
+    fn carve(
+        &self,
+        layer: &mut Layer<I>,
+        spawn_points: &[(i32, i32)],
+        blocked_cells: &HashSet<(i32, i32)>,
+        params: &GenerationParams,
+        rng: &mut dyn RngCore
+    ) {
+        // Walls are stored along with the direction their cell was carved in.
+        let mut frontier: Vec<((i32, i32), Dir, Option<Dir>)> = spawn_points.iter().filter(
+            |&&cell| layer.has(cell)
+        ).flat_map(
+            |&cell| layer.directions(cell).iter().map(move |&dir| (cell, dir, None))
+        ).collect();
+
+        while !frontier.is_empty() {
+            let index = rng.gen_range(0, frontier.len());
+            let (from, dir, previous) = frontier[index];
+            // Biases are applied by rejecting some of the picked walls.
+            let cell_params = params.at(from);
+            if !cell_params.is_unbiased() && !rng.gen_bool(
+                cell_params.weight(dir, previous) / cell_params.max_weight()
+            ) {
+                continue;
+            }
+            frontier.swap_remove(index);
+
+            let to = from + dir;
+            if layer.has(to)
+                && !blocked_cells.contains(&to)
+                && !layer.reachable(from, to)
+            {
+                layer.join(from, dir);
+                frontier.extend(layer.directions(to).iter().map(|&next| (to, next, Some(dir))));
+            }
+        }
+    }
+}
+
+impl<I: Default> CarvingAlgorithm<I> for Kruskal {
+    fn carve(
+        &self,
+        layer: &mut Layer<I>,
+        spawn_points: &[(i32, i32)],
+        blocked_cells: &HashSet<(i32, i32)>,
+        _params: &GenerationParams,
+        rng: &mut dyn RngCore
+    ) {
+        let region = CarvableRegion::new(layer, spawn_points.iter().copied(), blocked_cells);
+        region.connect_remaining(layer, rng);
+    }
+}
+
+impl<I: Default> CarvingAlgorithm<I> for Wilson {
+    fn carve(
+        &self,
+        layer: &mut Layer<I>,
+        spawn_points: &[(i32, i32)],
+        blocked_cells: &HashSet<(i32, i32)>,
+        _params: &GenerationParams,
+        rng: &mut dyn RngCore
+    ) {
+        let root = match spawn_points.first() {
+            Some(&root) if layer.has(root) => root,
+            _ => return,
+        };
+        // Random walks must be able to reach the tree, so only spawn points
+        // connected with the root are taken into account.
+        let region = CarvableRegion::new(
+            layer,
+            spawn_points.iter().copied().filter(|&cell| layer.reachable(root, cell)),
+            blocked_cells
+        );
+
+        let mut cells = region.order.clone();
+        cells.shuffle(rng);
+        let mut exits: HashMap<(i32, i32), Dir> = HashMap::new();
+        for start in cells {
+            // Random walk until the tree is hit. Only the last exit from every
+            // cell is remembered which erases loops.
+            let mut cell = start;
+            while !layer.reachable(cell, root) {
+                let dir = *region.moves(cell).choose(rng).unwrap();
+                exits.insert(cell, dir);
+                cell = cell + dir;
+            }
+
+            let mut cell = start;
+            while !layer.reachable(cell, root) {
+                let dir = exits[&cell];
+                if !layer.reachable(cell, cell + dir) {
+                    layer.join(cell, dir);
+                }
+                cell = cell + dir;
+            }
+            exits.clear();
+        }
+    }
+}
+
+impl<I: Default> CarvingAlgorithm<I> for Eller {
+    fn carve(
+        &self,
+        layer: &mut Layer<I>,
+        spawn_points: &[(i32, i32)],
+        blocked_cells: &HashSet<(i32, i32)>,
+        params: &GenerationParams,
+        rng: &mut dyn RngCore
+    ) {
+        const CHANCE_TO_JOIN_DOWN: f64 = 0.5;
+
+        let region = CarvableRegion::new(layer, spawn_points.iter().copied(), blocked_cells);
+        let mut rows: BTreeMap<i32, Vec<(i32, i32)>> = BTreeMap::new();
+        for &cell in &region.order {
+            rows.entry(cell.1).or_default().push(cell);
+        }
+
+        for row in rows.values_mut() {
+            row.sort();
+            for &cell in row.iter() {
+                let horizontal_weight = params.at(cell).horizontal_weight;
+                if region.allows(cell, Dir::RIGHT)
+                    && !layer.reachable(cell, cell + Dir::RIGHT)
+                    && rng.gen_bool(horizontal_weight / (1.0 + horizontal_weight))
+                {
+                    layer.join(cell, Dir::RIGHT);
+                }
+            }
+
+            // Every set gets at least one passage down if possible.
+            let mut sets: Vec<Vec<(i32, i32)>> = Vec::new();
+            for &cell in row.iter().filter(|&&cell| region.allows(cell, Dir::DOWN)) {
+                match sets.iter_mut().find(|set| layer.reachable(set[0], cell)) {
+                    Some(set) => set.push(cell),
+                    None => sets.push(vec![cell]),
+                }
+            }
+            for mut set in sets {
+                set.shuffle(rng);
+                for (i, &cell) in set.iter().enumerate() {
+                    if (i == 0 || rng.gen_bool(CHANCE_TO_JOIN_DOWN))
+                        && !layer.reachable(cell, cell + Dir::DOWN)
+                    {
+                        layer.join(cell, Dir::DOWN);
+                    }
+                }
+            }
+        }
+
+        // Arbitrary shapes can leave some sets without a way down,
+        // so they are merged afterwards.
+        region.connect_remaining(layer, rng);
+    }
+}
+
 
 #[test]
 fn test_generation() {
@@ -79,12 +636,121 @@ fn test_generation() {
     let mut rng = SmallRng::seed_from_u64(0);
     let shape = (-100..100).cartesian_product(-100..100).collect::<Vec<_>>();
     let mut layer = Layer::<()>::from_shape(&shape);
-    generate(&mut layer, std::iter::once((0, 0)), &HashSet::new(), &mut rng);
+    generate(
+        &mut layer, std::iter::once((0, 0)), &HashSet::new(),
+        &GenerationParams::default(), &mut rng
+    );
     for &(x, y) in &shape {
         assert!(layer.reachable((0, 0), (x, y)));
     }
 }
 
+#[test]
+fn test_carving_algorithms() {
+    use rand::SeedableRng;
+    use rand::rngs::SmallRng;
+    use itertools::Itertools;
+
+    let algorithms: [&dyn CarvingAlgorithm<()>; 7] = [
+        &GrowingTree(), &LegacyGrowingTree(), &RecursiveBacktracker(), &Prim(),
+        &Kruskal(), &Wilson(), &Eller(),
+    ];
+    let shape = (-20..20).cartesian_product(-20..20).collect::<Vec<_>>();
+    let blocked_cells: HashSet<_> = (-5..5).cartesian_product(-5..5).collect();
+    let mut biased_params = GenerationParams::new(CellParams {
+        branching: 0.2,
+        straightness: 2.0,
+        horizontal_weight: 3.0,
+        ..Default::default()
+    });
+    biased_params.override_cells(
+        (0..20).cartesian_product(0..20),
+        CellParams { horizontal_weight: 0.3, ..Default::default() }
+    );
+
+    let topologies = [Topology::Square, Topology::Hex, Topology::Triangle];
+    for (params, &topology) in [GenerationParams::default(), biased_params].iter()
+        .cartesian_product(&topologies)
+    {
+        for (i, algorithm) in algorithms.iter().enumerate() {
+            let mut rng = SmallRng::seed_from_u64(0);
+            let mut layer = Layer::<()>::from_shape_with_topology(&shape, topology);
+            algorithm.carve(&mut layer, &[(10, 10)], &blocked_cells, params, &mut rng);
+
+            let mut passages = 0;
+            for &cell in &shape {
+                for dir in topology.forward_directions(cell) {
+                    if layer.passable(cell, dir) {
+                        passages += 1;
+                        assert!(!blocked_cells.contains(&cell) && !blocked_cells.contains(&(cell + dir)),
+                            "algorithm #{} carved into a blocked cell", i);
+                    }
+                }
+                assert_eq!(layer.reachable((10, 10), cell), !blocked_cells.contains(&cell),
+                    "algorithm #{} left {:?} unreachable", i, cell);
+            }
+            // A tree has exactly one passage less than cells.
+            assert_eq!(passages, shape.len() - blocked_cells.len() - 1,
+                "algorithm #{} created a loop", i);
+        }
+    }
+}
+
+#[test]
+fn test_legacy_generation_is_stable() {
+    use rand::SeedableRng;
+    use rand::rngs::SmallRng;
+    use crate::geometry_sets::make_circle;
+
+    let mut rng = SmallRng::seed_from_u64(0);
+    let shape: Vec<_> = make_circle(30).collect();
+    let blocked_cells: HashSet<_> = make_circle(5).map(|(x, y)| (x + 10, y)).collect();
+    let mut layer = Layer::<()>::from_shape(&shape);
+    LegacyGrowingTree().carve(
+        &mut layer, &[(0, 0), (-20, 0)], &blocked_cells,
+        &GenerationParams::default(), &mut rng
+    );
+
+    let mut fingerprint: u64 = 0;
+    for &(x, y) in &shape {
+        for (k, &dir) in [Dir::RIGHT, Dir::DOWN].iter().enumerate() {
+            if layer.passable((x, y), dir) {
+                let value = (x + 100) * 1000 + (y + 100) * 2 + k as i32;
+                fingerprint = fingerprint.wrapping_mul(31).wrapping_add(value as u64);
+            }
+        }
+    }
+    // Computed with the original implementation.
+    assert_eq!(fingerprint, 11796478612643386876);
+}
+
+#[test]
+fn test_braid() {
+    use rand::SeedableRng;
+    use rand::rngs::SmallRng;
+    use itertools::Itertools;
+
+    let mut rng = SmallRng::seed_from_u64(0);
+    let shape = (-20..20).cartesian_product(-20..20).collect::<Vec<_>>();
+    let blocked_cells: HashSet<_> = (-5..5).cartesian_product(-5..5).collect();
+    let params = GenerationParams::new(CellParams { braid: 1.0, ..Default::default() });
+    let mut layer = Layer::<()>::from_shape(&shape);
+    generate(&mut layer, std::iter::once((10, 10)), &blocked_cells, &params, &mut rng);
+    braid(&mut layer, shape.iter().copied(), &blocked_cells, &params, &mut rng);
+
+    let mut passages = 0;
+    for &cell in &shape {
+        let exits = layer.directions(cell).iter().filter(|&&dir| layer.passable(cell, dir)).count();
+        if blocked_cells.contains(&cell) {
+            assert_eq!(exits, 0);
+        } else {
+            assert!(exits > 1, "{:?} is still a dead end", cell);
+        }
+        passages += exits;
+    }
+    assert!(passages / 2 > shape.len() - blocked_cells.len() - 1);
+}
+
 #[cfg(feature = "bench")]
 mod benches {
 
@@ -93,7 +759,7 @@ extern crate test;
 use rand::rngs::SmallRng;
 use rand::SeedableRng;
 
-use crate::build::make_circle;
+use crate::geometry_sets::make_circle;
 use crate::layer::Layer;
 
 #[bench]
@@ -109,6 +775,7 @@ fn bench_generation(b: &mut test::Bencher) {
             &mut layer.clone(),
             std::iter::once((0, 0)),
             &blocked_cells,
+            &Default::default(),
             &mut rng
         );
     });
@@ -1,12 +1,24 @@
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+use itertools::Itertools;
+
+#[allow(clippy::upper_case_acronyms, non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub enum Dir {
     RIGHT,
     DOWN,
     LEFT,
     UP,
+    /// Only used by hexagonal grids.
+    UP_RIGHT,
+    /// Only used by hexagonal grids.
+    DOWN_LEFT,
 }
 
 pub const DIRECTIONS: [Dir; 4] = [Dir::UP, Dir::RIGHT, Dir::DOWN, Dir::LEFT];
+const HEX_DIRECTIONS: [Dir; 6] = [
+    Dir::UP, Dir::UP_RIGHT, Dir::RIGHT, Dir::DOWN, Dir::DOWN_LEFT, Dir::LEFT
+];
+const UPWARD_TRIANGLE_DIRECTIONS: [Dir; 3] = [Dir::RIGHT, Dir::DOWN, Dir::LEFT];
+const DOWNWARD_TRIANGLE_DIRECTIONS: [Dir; 3] = [Dir::UP, Dir::RIGHT, Dir::LEFT];
 
 impl Dir {
     pub fn opposite(self) -> Dir {
@@ -15,37 +27,22 @@ impl Dir {
             Dir::DOWN => Dir::UP,
             Dir::LEFT => Dir::RIGHT,
             Dir::UP => Dir::DOWN,
-        }
-    }
-
-    pub fn rotate_clockwise(self) -> Dir {
-        match self {
-            Dir::RIGHT => Dir::DOWN,
-            Dir::DOWN => Dir::LEFT,
-            Dir::LEFT => Dir::UP,
-            Dir::UP => Dir::RIGHT,
-        }
-    }
-
-    #[allow(dead_code)]
-    pub fn rotate_anticlockwise(self) -> Dir {
-        match self {
-            Dir::RIGHT => Dir::UP,
-            Dir::DOWN => Dir::RIGHT,
-            Dir::LEFT => Dir::DOWN,
-            Dir::UP => Dir::LEFT,
+            Dir::UP_RIGHT => Dir::DOWN_LEFT,
+            Dir::DOWN_LEFT => Dir::UP_RIGHT,
         }
     }
 }
 
 /// Uses screen coordinate system - X axis points right and Y axis points down.
-impl Into<(i32, i32)> for Dir {
-    fn into(self) -> (i32, i32) {
-        match self {
+impl From<Dir> for (i32, i32) {
+    fn from(dir: Dir) -> (i32, i32) {
+        match dir {
             Dir::RIGHT => (1, 0),
             Dir::DOWN => (0, 1),
             Dir::LEFT => (-1, 0),
-            Dir::UP => (0, -1)
+            Dir::UP => (0, -1),
+            Dir::UP_RIGHT => (1, -1),
+            Dir::DOWN_LEFT => (-1, 1),
         }
     }
 }
@@ -59,6 +56,121 @@ impl std::ops::Add<Dir> for (i32, i32) {
     }
 }
 
+/// The way cells of a layer are arranged on a plane.
+///
+/// Every topology uses integer pairs for cell coordinates and `Dir` for moves,
+/// but the set of valid moves and the placement of cells on the screen differ.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, Default, serde::Serialize, serde::Deserialize
+)]
+pub enum Topology {
+    #[default]
+    Square,
+    /// Pointy-topped hexagons in axial coordinates: the second coordinate
+    /// is a row, and every row is shifted half a cell to the right of the
+    /// previous one. `UP_RIGHT` and `DOWN_LEFT` are used along with the
+    /// four usual directions.
+    Hex,
+    /// Triangles pointing up and down alternately. A triangle is pointing up
+    /// if the sum of its coordinates is even. Triangles pointing up have
+    /// neighbours on the left, on the right and below them, while triangles
+    /// pointing down have them on the left, on the right and above them.
+    Triangle,
+}
+
+fn points_up((x, y): (i32, i32)) -> bool {
+    (x + y).rem_euclid(2) == 0
+}
+
+impl Topology {
+    /// Directions to all the neighbours of `coord` listed clockwise.
+    pub fn directions(self, coord: (i32, i32)) -> &'static [Dir] {
+        match self {
+            Topology::Square => &DIRECTIONS,
+            Topology::Hex => &HEX_DIRECTIONS,
+            Topology::Triangle => if points_up(coord) {
+                &UPWARD_TRIANGLE_DIRECTIONS
+            } else {
+                &DOWNWARD_TRIANGLE_DIRECTIONS
+            },
+        }
+    }
+
+    pub fn has_direction(self, coord: (i32, i32), dir: Dir) -> bool {
+        self.directions(coord).contains(&dir)
+    }
+
+    /// Directions which lead to the neighbours coming after `coord`
+    /// in lexicographical order. Enumerating them for every cell lists
+    /// each pair of neighbours exactly once.
+    pub fn forward_directions(self, coord: (i32, i32)) -> impl Iterator<Item=Dir> {
+        self.directions(coord).iter().copied().filter(move |&dir| coord < coord + dir)
+    }
+
+    /// The next direction after `dir` among the ones valid at `coord`
+    /// going clockwise.
+    pub fn rotate_clockwise(self, coord: (i32, i32), dir: Dir) -> Dir {
+        let directions = self.directions(coord);
+        let index = directions.iter().position(|&d| d == dir)
+            .expect("Direction is not valid for the cell");
+        directions[(index + 1) % directions.len()]
+    }
+
+    /// The next direction after `dir` among the ones valid at `coord`
+    /// going counterclockwise.
+    pub fn rotate_counterclockwise(self, coord: (i32, i32), dir: Dir) -> Dir {
+        let directions = self.directions(coord);
+        let index = directions.iter().position(|&d| d == dir)
+            .expect("Direction is not valid for the cell");
+        directions[(index + directions.len() - 1) % directions.len()]
+    }
+
+    /// Position of the center of the cell on the screen. The distance
+    /// between centers of neighbouring cells is always `1.0`.
+    pub fn center(self, (x, y): (i32, i32)) -> (f32, f32) {
+        let sqrt_3 = 3_f32.sqrt();
+        match self {
+            Topology::Square => (x as f32, y as f32),
+            Topology::Hex => (x as f32 + y as f32 / 2.0, y as f32 * sqrt_3 / 2.0),
+            Topology::Triangle => (
+                x as f32 * sqrt_3 / 2.0,
+                y as f32 * 1.5 + if points_up((x, y)) { 0.25 } else { -0.25 }
+            ),
+        }
+    }
+
+    /// Vertices of the cell relative to its center listed clockwise.
+    pub fn vertices(self, coord: (i32, i32)) -> Vec<(f32, f32)> {
+        let sqrt_3 = 3_f32.sqrt();
+        match self {
+            Topology::Square => vec![(-0.5, -0.5), (0.5, -0.5), (0.5, 0.5), (-0.5, 0.5)],
+            Topology::Hex => {
+                let (w, h) = (0.5, 0.5 / sqrt_3);
+                vec![(0.0, -2.0 * h), (w, -h), (w, h), (0.0, 2.0 * h), (-w, h), (-w, -h)]
+            },
+            Topology::Triangle => if points_up(coord) {
+                vec![(0.0, -1.0), (sqrt_3 / 2.0, 0.5), (-sqrt_3 / 2.0, 0.5)]
+            } else {
+                vec![(-sqrt_3 / 2.0, -0.5), (sqrt_3 / 2.0, -0.5), (0.0, 1.0)]
+            },
+        }
+    }
+
+    /// Offsets of the cells which may share at least a corner with a cell.
+    /// Includes the cell itself. For triangles, the offsets are the same
+    /// for both orientations, so a few cells only touching the neighbours
+    /// are included too.
+    pub fn touching_offsets(self) -> Vec<(i32, i32)> {
+        match self {
+            Topology::Square => (-1..=1).cartesian_product(-1..=1).collect(),
+            Topology::Hex => std::iter::once((0, 0))
+                .chain(HEX_DIRECTIONS.iter().map(|&dir| dir.into()))
+                .collect(),
+            Topology::Triangle => (-2..=2).cartesian_product(-1..=1).collect(),
+        }
+    }
+}
+
 #[test]
 fn test_add_dir_to_tuple() {
     assert_eq!((0, 1) + Dir::UP, (0, 0));
@@ -66,3 +178,23 @@ fn test_add_dir_to_tuple() {
     assert_eq!((-1, -1) + Dir::DOWN, (-1, 0));
     assert_eq!((-2, 1) + Dir::LEFT, (-3, 1));
 }
+
+#[test]
+fn test_topology_neighbours() {
+    use crate::utils::tuple_arithmetic::distance;
+
+    for &topology in &[Topology::Square, Topology::Hex, Topology::Triangle] {
+        for coord in (-3..3).cartesian_product(-3..3) {
+            let center = topology.center(coord);
+            for &dir in topology.directions(coord) {
+                let to = coord + dir;
+                assert!(topology.has_direction(to, dir.opposite()));
+                assert!((distance(center, topology.center(to)) - 1.0).abs() < 1e-4);
+                assert_ne!(
+                    topology.forward_directions(coord).any(|d| d == dir),
+                    topology.forward_directions(to).any(|d| d == dir.opposite())
+                );
+            }
+        }
+    }
+}
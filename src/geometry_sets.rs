@@ -28,7 +28,7 @@ pub fn erose(a: &HashSet<(i32, i32)>, b: &HashSet<(i32, i32)>) -> HashSet<(i32,
 }
 
 fn get_border(a: &HashSet<(i32, i32)>) -> HashSet<(i32, i32)> {
-    let erosed = erose(&a, &(-1..=1).cartesian_product(-1..=1).collect());
+    let erosed = erose(a, &(-1..=1).cartesian_product(-1..=1).collect());
     a.difference(&erosed).copied().collect()
 }
 
@@ -43,6 +43,12 @@ pub fn make_circle(radius: i32) -> impl Iterator<Item=(i32, i32)> {
         })
 }
 
+/// Makes a hexagon for `Topology::Hex` layers.
+pub fn make_hexagon(radius: i32) -> impl Iterator<Item=(i32, i32)> {
+    (-radius..=radius).cartesian_product(-radius..=radius)
+        .filter(move |&(x, y)| x.abs() < radius && y.abs() < radius && (x + y).abs() < radius)
+}
+
 pub fn make_ring(inner_radius: i32, outer_radius: i32) -> impl Iterator<Item=(i32, i32)> {
     (-outer_radius..=outer_radius).cartesian_product(-outer_radius..=outer_radius)
         .filter_map(move |(x, y)| {
@@ -1,38 +1,55 @@
-use disjoint_sets::UnionFind;
-use crate::geometry::Dir;
+use std::collections::{HashMap, HashSet, VecDeque};
+use crate::geometry::{Dir, Topology};
+use crate::utils::bit_set::BitSet;
+use crate::utils::disjoint_sets::DisjointSets;
 
 
-#[derive(Default, Clone, Copy)]
-struct Cell<Info> {
-    has_passage_right: bool,
-    has_passage_down: bool,
-    info: Info,
-}
+/// Passages are stored only for the directions leading to the cells
+/// with greater indices. The others are looked up in the neighbouring cells.
+const PASSAGE_DIRECTIONS: [Dir; 3] = [Dir::RIGHT, Dir::DOWN, Dir::UP_RIGHT];
 
-impl<Info> Cell<Info> {
-    fn get_passage_mut(&mut self, dir: Dir) -> &mut bool {
-        match dir {
-            Dir::RIGHT => &mut self.has_passage_right,
-            Dir::DOWN => &mut self.has_passage_down,
-            Dir::UP => panic!("Cell has no passage up"),
-            Dir::LEFT => panic!("Cell has no passage left"),
-        }
+fn passage_plane(dir: Dir) -> usize {
+    match dir {
+        Dir::RIGHT => 0,
+        Dir::DOWN => 1,
+        Dir::UP_RIGHT => 2,
+        Dir::UP => panic!("Cell has no passage up"),
+        Dir::LEFT => panic!("Cell has no passage left"),
+        Dir::DOWN_LEFT => panic!("Cell has no passage down-left"),
     }
 }
 
 
-#[derive(Clone)]
+/// All the data is stored in arrays covering the bounding box of the layer.
+/// Properties of cells are packed into bit sets, and infos are kept apart
+/// from them, so that passages of neighbouring cells are close in memory.
+///
+/// Reachability is not serialized, it is rebuilt from the passages on load.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(remote = "Self")]
 pub struct Layer<CellInfo: Default> {
-    cells: Vec<Option<Cell<CellInfo>>>,
+    present: BitSet,
+    passages: [BitSet; PASSAGE_DIRECTIONS.len()],
+    infos: Vec<CellInfo>,
     min_i: i32,
     min_j: i32,
     stride: i32,
     height: i32,
-    dsu: UnionFind<usize>,
+    topology: Topology,
+    #[serde(skip)]
+    dsu: DisjointSets,
+    /// Pairs of cells made reachable by `treat_as_reachable`.
+    /// They are needed for recalculating reachability after `separate`.
+    links: HashMap<(i32, i32), Vec<(i32, i32)>>,
 }
 
 impl<CellInfo: Default> Layer<CellInfo> {
+    #[allow(dead_code)]
     pub fn from_shape(coords: &[(i32, i32)]) -> Self {
+        Self::from_shape_with_topology(coords, Topology::Square)
+    }
+
+    pub fn from_shape_with_topology(coords: &[(i32, i32)], topology: Topology) -> Self {
         assert!(!coords.is_empty());
         let &(min_i, _j) = coords.iter().min_by_key(|(i, _j)| i).unwrap();
         let &(_i, min_j) = coords.iter().min_by_key(|(_i, j)| j).unwrap();
@@ -43,13 +60,16 @@ impl<CellInfo: Default> Layer<CellInfo> {
         let len = (height * stride) as usize;
 
         let mut result = Self {
-            cells: std::iter::repeat_with(|| None).take(len).collect(),
-            min_i, min_j, stride, height,
-            dsu: UnionFind::new(len),
+            present: BitSet::new(len),
+            passages: [BitSet::new(len), BitSet::new(len), BitSet::new(len)],
+            infos: std::iter::repeat_with(Default::default).take(len).collect(),
+            min_i, min_j, stride, height, topology,
+            dsu: DisjointSets::new(len),
+            links: HashMap::new(),
         };
         for &coord in coords {
             let index = result.index(coord).unwrap();
-            result.cells[index] = Some(Default::default());
+            result.present.set(index, true);
         }
         result
     }
@@ -71,58 +91,79 @@ impl<CellInfo: Default> Layer<CellInfo> {
         )
     }
 
-    fn get(&self, coord: (i32, i32)) -> Option<&Cell<CellInfo>> {
-        let index = self.index(coord)?;
-        self.cells.get(index).and_then(|x| x.as_ref())
-    }
-
-    fn get_mut(&mut self, coord: (i32, i32)) -> Option<&mut Cell<CellInfo>> {
-        let index = self.index(coord)?;
-        self.cells.get_mut(index).and_then(|x| x.as_mut())
+    /// Index of the cell if it belongs to the layer.
+    fn present_index(&self, coord: (i32, i32)) -> Option<usize> {
+        self.index(coord).filter(|&index| self.present.get(index))
     }
 
     pub fn get_info(&self, coord: (i32, i32)) -> Option<&CellInfo> {
-        self.get(coord).map(|cell| &cell.info)
+        let index = self.present_index(coord)?;
+        Some(&self.infos[index])
     }
 
     pub fn get_info_mut(&mut self, coord: (i32, i32)) -> Option<&mut CellInfo> {
-        self.get_mut(coord).map(|cell| &mut cell.info)
+        let index = self.present_index(coord)?;
+        Some(&mut self.infos[index])
+    }
+
+    pub fn topology(&self) -> Topology {
+        self.topology
+    }
+
+    /// Directions to all the neighbours of a cell listed clockwise.
+    pub fn directions(&self, coord: (i32, i32)) -> &'static [Dir] {
+        self.topology.directions(coord)
     }
 
     pub fn has(&self, coord: (i32, i32)) -> bool {
-        self.get(coord).is_some()
+        self.present_index(coord).is_some()
+    }
+
+    /// All the cells of the layer.
+    pub fn cells(&self) -> impl Iterator<Item=(i32, i32)> + '_ {
+        (0..self.infos.len())
+            .filter(move |&index| self.present.get(index))
+            .map(move |index| self.pos_from_index(index))
     }
 
     pub fn passable(&self, from: (i32, i32), dir: Dir) -> bool {
-        let cell = match self.get(from) {
+        let index = match self.present_index(from) {
             None => return false,
             Some(value) => value,
         };
+        if !self.topology.has_direction(from, dir) {
+            return false;
+        }
         match dir {
-            Dir::LEFT | Dir::UP =>
+            Dir::LEFT | Dir::UP | Dir::DOWN_LEFT =>
                 self.passable(
                     from + dir,
                     dir.opposite()
                 ),
-            Dir::RIGHT => cell.has_passage_right,
-            Dir::DOWN => cell.has_passage_down,
+            Dir::RIGHT | Dir::DOWN | Dir::UP_RIGHT =>
+                self.passages[passage_plane(dir)].get(index),
         }
     }
 
     pub fn join(&mut self, from: (i32, i32), dir: Dir) {
+        assert!(
+            self.topology.has_direction(from, dir),
+            "Trying to join in a direction not supported by the topology"
+        );
         match dir {
-            Dir::LEFT | Dir::UP =>
+            Dir::LEFT | Dir::UP | Dir::DOWN_LEFT =>
                 self.join(
                     from + dir,
                     dir.opposite()
                 ),
-            Dir::RIGHT | Dir::DOWN => {
+            Dir::RIGHT | Dir::DOWN | Dir::UP_RIGHT => {
                 let to = from + dir;
                 const MSG: &str = "Trying to join with cell outside the layer";
 
-                let cell = self.get_mut(from).expect(MSG);
-                *cell.get_passage_mut(dir) = true;
-                self.dsu.union(self.index(from).unwrap(), self.index(to).expect(MSG));
+                let from_index = self.present_index(from).expect(MSG);
+                let to_index = self.index(to).expect(MSG);
+                self.passages[passage_plane(dir)].set(from_index, true);
+                self.dsu.union(from_index, to_index);
             }
         }
     }
@@ -137,6 +178,80 @@ impl<CellInfo: Default> Layer<CellInfo> {
 
     pub fn treat_as_reachable(&mut self, a: (i32, i32), b: (i32, i32)) {
         self.dsu.union(self.index(a).unwrap(), self.index(b).unwrap());
+        self.links.entry(a).or_default().push(b);
+        self.links.entry(b).or_default().push(a);
+    }
+
+    /// Puts the wall between `from` and its neighbour in `dir` back.
+    ///
+    /// Reachability is recalculated only for the cells which were reachable
+    /// from `from`, so the cost depends on the size of that area only.
+    #[allow(dead_code)]
+    pub fn separate(&mut self, from: (i32, i32), dir: Dir) {
+        assert!(self.passable(from, dir), "Trying to separate cells which are not joined");
+        match dir {
+            Dir::LEFT | Dir::UP | Dir::DOWN_LEFT =>
+                self.separate(
+                    from + dir,
+                    dir.opposite()
+                ),
+            Dir::RIGHT | Dir::DOWN | Dir::UP_RIGHT => {
+                let to = from + dir;
+                let index = self.index(from).unwrap();
+                self.passages[passage_plane(dir)].set(index, false);
+
+                let first_part = match self.connected_cells(from, to) {
+                    Some(cells) => cells,
+                    // There is another way between the cells.
+                    None => return,
+                };
+                let second_part = self.connected_cells(to, from).unwrap();
+                for part in &[first_part, second_part] {
+                    for &cell in part {
+                        self.dsu.reset(self.index(cell).unwrap());
+                    }
+                    let root = self.index(part[0]).unwrap();
+                    for &cell in part {
+                        self.dsu.union(root, self.index(cell).unwrap());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Finds all the cells connected with `from` by passages or by
+    /// `treat_as_reachable`. Returns `None` as soon as `target` is found.
+    fn connected_cells(&self, from: (i32, i32), target: (i32, i32)) -> Option<Vec<(i32, i32)>> {
+        let mut result = vec![from];
+        let mut visited = HashSet::new();
+        visited.insert(from);
+        let mut queue = VecDeque::new();
+        queue.push_back(from);
+        while let Some(cell) = queue.pop_front() {
+            let neighbours = self.directions(cell).iter()
+                .filter(|&&dir| self.passable(cell, dir))
+                .map(|&dir| cell + dir)
+                .chain(self.links.get(&cell).into_iter().flatten().copied());
+            for to in neighbours {
+                if to == target {
+                    return None;
+                }
+                if visited.insert(to) {
+                    result.push(to);
+                    queue.push_back(to);
+                }
+            }
+        }
+        Some(result)
+    }
+
+    /// Approximate amount of memory used by the layer on the heap.
+    #[cfg(feature = "bench")]
+    pub fn heap_size(&self) -> usize {
+        self.present.heap_size()
+            + self.passages.iter().map(BitSet::heap_size).sum::<usize>()
+            + self.infos.capacity() * std::mem::size_of::<CellInfo>()
+            + self.dsu.heap_size()
     }
 
     pub fn map<ResultInfo: Default>(
@@ -144,31 +259,89 @@ impl<CellInfo: Default> Layer<CellInfo> {
         f: impl Fn(&CellInfo, (i32, i32)) -> ResultInfo
     ) -> Layer<ResultInfo> {
         Layer {
-            cells: self.cells.iter().enumerate().map(|(index, opt)| opt.as_ref().map(
-                |cell| Cell{
-                    has_passage_right: cell.has_passage_right,
-                    has_passage_down: cell.has_passage_down,
-                    info: f(&cell.info, self.pos_from_index(index))
+            present: self.present.clone(),
+            passages: self.passages.clone(),
+            infos: self.infos.iter().enumerate().map(|(index, info)| {
+                if self.present.get(index) {
+                    f(info, self.pos_from_index(index))
+                } else {
+                    Default::default()
                 }
-            )).collect(),
+            }).collect(),
             min_i: self.min_i,
             min_j: self.min_j,
             stride: self.stride,
             height: self.height,
-            dsu: self.dsu.clone()
+            topology: self.topology,
+            dsu: self.dsu.clone(),
+            links: self.links.clone(),
+        }
+    }
+}
+
+impl<CellInfo: Default + serde::Serialize> serde::Serialize for Layer<CellInfo> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Self::serialize(self, serializer)
+    }
+}
+
+impl<'de, CellInfo> serde::Deserialize<'de> for Layer<CellInfo>
+    where CellInfo: Default + serde::Deserialize<'de>
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let mut layer = Self::deserialize(deserializer)?;
+        let len = (layer.height * layer.stride) as usize;
+        let fits = layer.height >= 0 && layer.stride >= 0
+            && layer.infos.len() == len
+            && layer.present.capacity() >= len
+            && layer.passages.iter().all(|passages| passages.capacity() >= len);
+        if !fits {
+            return Err(D::Error::custom("layer data doesn't match its size"));
+        }
+        if layer.links.iter().flat_map(|(&a, bs)| bs.iter().map(move |&b| (a, b)))
+            .any(|(a, b)| layer.index(a).is_none() || layer.index(b).is_none())
+        {
+            return Err(D::Error::custom("layer links cells outside of it"));
+        }
+
+        let mut dsu = DisjointSets::new(len);
+        for index in (0..len).filter(|&index| layer.present.get(index)) {
+            let from = layer.pos_from_index(index);
+            for &dir in &PASSAGE_DIRECTIONS {
+                if layer.topology.has_direction(from, dir)
+                    && layer.passages[passage_plane(dir)].get(index)
+                {
+                    let to = layer.index(from + dir)
+                        .ok_or_else(|| D::Error::custom("passage leads outside of the layer"))?;
+                    dsu.union(index, to);
+                }
+            }
         }
+        for (&a, bs) in &layer.links {
+            for &b in bs {
+                dsu.union(layer.index(a).unwrap(), layer.index(b).unwrap());
+            }
+        }
+        layer.dsu = dsu;
+        Ok(layer)
     }
 }
 
 impl<CellInfo: Default> Default for Layer<CellInfo> {
     fn default() -> Self {
         Self {
-            cells: Default::default(),
+            present: Default::default(),
+            passages: Default::default(),
+            infos: Default::default(),
             min_i: 0,
             min_j: 0,
             stride: 0,
             height: 0,
-            dsu: Default::default()
+            topology: Default::default(),
+            dsu: Default::default(),
+            links: Default::default(),
         }
     }
 }
@@ -203,6 +376,52 @@ fn test_layer() {
     assert!(!layer.reachable((-1, -2), (-1, 0)));
 }
 
+#[test]
+fn test_separate() {
+    use itertools::Itertools;
+
+    let shape = (0..3).cartesian_product(0..2).collect::<Vec<_>>();
+    let mut layer = Layer::<()>::from_shape(&shape);
+    // A loop around (0, 0), (1, 0), (1, 1), (0, 1) and a tail to (2, 0).
+    layer.join((0, 0), Dir::RIGHT);
+    layer.join((1, 0), Dir::DOWN);
+    layer.join((1, 1), Dir::LEFT);
+    layer.join((0, 1), Dir::UP);
+    layer.join((1, 0), Dir::RIGHT);
+
+    layer.separate((1, 0), Dir::LEFT);
+    assert!(!layer.passable((0, 0), Dir::RIGHT));
+    assert!(layer.reachable((0, 0), (2, 0)));
+
+    layer.separate((1, 1), Dir::UP);
+    assert!(layer.reachable((0, 0), (1, 1)));
+    assert!(layer.reachable((1, 0), (2, 0)));
+    assert!(!layer.reachable((0, 0), (1, 0)));
+
+    // Cells linked explicitly stay reachable.
+    layer.treat_as_reachable((0, 0), (2, 1));
+    layer.join((2, 1), Dir::UP);
+    layer.separate((2, 0), Dir::DOWN);
+    assert!(layer.reachable((1, 1), (2, 1)));
+    assert!(!layer.reachable((2, 1), (2, 0)));
+}
+
+#[test]
+fn test_triangle_layer() {
+    use itertools::Itertools;
+
+    let shape = (0..4).cartesian_product(0..2).collect::<Vec<_>>();
+    let mut layer = Layer::<()>::from_shape_with_topology(&shape, Topology::Triangle);
+
+    // (0, 0) points up and (0, 1) points down, so they share a side.
+    layer.join((0, 1), Dir::UP);
+    assert!(layer.passable((0, 0), Dir::DOWN));
+    assert!(layer.reachable((0, 0), (0, 1)));
+    // (1, 0) points down, so there is no side below it.
+    assert!(!layer.passable((1, 0), Dir::DOWN));
+    assert!(!layer.passable((1, 1), Dir::UP));
+}
+
 #[test]
 fn test_indexing() {
     use itertools::Itertools;
@@ -216,3 +435,70 @@ fn test_indexing() {
         }
     }
 }
+
+#[cfg(feature = "bench")]
+mod benches {
+
+extern crate test;
+
+use itertools::Itertools;
+use crate::geometry::Dir;
+use crate::layer::Layer;
+
+const SIZE: i32 = 1000;
+
+fn make_layer() -> Layer<()> {
+    let shape = (0..SIZE).cartesian_product(0..SIZE).collect::<Vec<_>>();
+    Layer::from_shape(&shape)
+}
+
+fn join_all(layer: &mut Layer<()>) {
+    for x in 0..SIZE {
+        for y in 0..SIZE {
+            if y + 1 < SIZE {
+                layer.join((x, y), Dir::DOWN);
+            }
+        }
+        if x + 1 < SIZE {
+            layer.join((x, 0), Dir::RIGHT);
+        }
+    }
+}
+
+#[bench]
+fn bench_from_shape_1000x1000(b: &mut test::Bencher) {
+    b.iter(make_layer);
+}
+
+#[bench]
+fn bench_clone_1000x1000(b: &mut test::Bencher) {
+    let layer = make_layer();
+    // Most of it is taken by the disjoint sets, the bit sets take a few bits per cell.
+    assert!(layer.heap_size() < 6 * (SIZE * SIZE) as usize);
+    b.iter(|| layer.clone());
+}
+
+#[bench]
+fn bench_join_1000x1000(b: &mut test::Bencher) {
+    let layer = make_layer();
+    b.iter(|| join_all(&mut layer.clone()));
+}
+
+#[bench]
+fn bench_passable_1000x1000(b: &mut test::Bencher) {
+    let mut layer = make_layer();
+    join_all(&mut layer);
+    b.iter(|| {
+        let mut count = 0;
+        for x in 0..SIZE {
+            for y in 0..SIZE {
+                if layer.passable((x, y), Dir::DOWN) && layer.reachable((x, y), (0, 0)) {
+                    count += 1;
+                }
+            }
+        }
+        count
+    });
+}
+
+}
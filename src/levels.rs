@@ -1,17 +1,26 @@
+use std::time::{Duration, Instant};
+
 use rand::rngs::SmallRng;
 use rand::SeedableRng;
 use rand::prelude::*;
 use itertools::Itertools;
 
 use crate::build::{MazeBuilder, GenerationError};
+use crate::generation::{
+    GenerationParams, CellParams,
+    LegacyGrowingTree, RecursiveBacktracker,
+};
+use crate::geometry::Topology;
 use crate::geometry_sets::{
     make_circle,
+    make_hexagon,
     make_ring,
     make_lemniscate,
     make_hourglass,
 };
 use crate::maze::Maze;
-use crate::visible_area::visibility_radius;
+use crate::chunked_layer::ChunkedLayer;
+use crate::visible_area::DEFAULT_VISIBILITY_RADIUS;
 
 
 pub trait LevelGenerator: Send + Sync {
@@ -21,25 +30,96 @@ pub trait LevelGenerator: Send + Sync {
 
     fn intro_text(&self) -> &'static str;
 
-    fn try_generate(&self, stage: u32, rng: &mut SmallRng) -> Result<Maze, GenerationError>;
-
-    fn generate(&self, stage: u32) -> Maze {
-        let mut rng = SmallRng::seed_from_u64(u64::from(stage));
-        loop {
-            match self.try_generate(stage, &mut rng) {
-                Ok(maze) => return maze,
-                Err(error) => eprintln!(
-                    "Could not generate level \"{}\" on stage {}: {}",
-                    self.id(), stage, error
-                ),
+    /// Parameters of carving used on the given stage.
+    fn generation_params(&self, _stage: u32) -> GenerationParams {
+        GenerationParams::default()
+    }
+
+    /// How many cells around the player are visible on the given stage.
+    fn visibility_radius(&self, _stage: u32) -> i32 {
+        DEFAULT_VISIBILITY_RADIUS
+    }
+
+    /// Generates a maze for the given stage carved with `params`.
+    fn try_generate(
+        &self, stage: u32, params: GenerationParams, rng: &mut SmallRng
+    ) -> Result<Maze, GenerationError>;
+
+    /// Generates a maze for the given stage with the default retry policy.
+    /// The stage determines the difficulty and `seed` picks one of the mazes
+    /// of that difficulty.
+    fn generate(&self, stage: u32, seed: u64) -> Result<Maze, GenerationError> {
+        self.generate_with(stage, seed, &RetryPolicy::default(), &mut |_| {})
+    }
+
+    /// Generates a maze for the given stage retrying failed attempts as
+    /// `policy` allows. Every failed attempt is passed to `on_failure`.
+    fn generate_with(
+        &self,
+        stage: u32,
+        seed: u64,
+        policy: &RetryPolicy,
+        on_failure: &mut dyn FnMut(&GenerationError),
+    ) -> Result<Maze, GenerationError> {
+        let started = Instant::now();
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let mut attempts = 0;
+        let mut last_error = None;
+        for &stage in std::iter::once(&stage).chain(&policy.fallback_stage) {
+            for _ in 0..policy.max_attempts {
+                if policy.time_budget.is_some_and(|budget| started.elapsed() >= budget) {
+                    break;
+                }
+                attempts += 1;
+                match self.try_generate(stage, self.generation_params(stage), &mut rng) {
+                    Ok(maze) => return Ok(maze),
+                    Err(error) => {
+                        eprintln!(
+                            "Could not generate level \"{}\" on stage {} with seed {}: {}",
+                            self.id(), stage, seed, error
+                        );
+                        on_failure(&error);
+                        last_error = Some(error);
+                    },
+                }
             }
         }
+        Err(GenerationError::GaveUp {
+            attempts,
+            last_error: last_error.map(Box::new),
+        })
+    }
+}
+
+
+/// How many times and for how long `LevelGenerator::generate_with` tries
+/// to generate a maze before giving up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts made on every stage.
+    pub max_attempts: u32,
+    /// No attempts are made after this much time has passed. Which attempt
+    /// succeeds must not depend on how fast the machine is, so mazes of seed
+    /// codes are generated without a budget.
+    pub time_budget: Option<Duration>,
+    /// An easier stage tried if the requested one keeps failing. Earlier
+    /// stages have fewer layers, so they fail less often.
+    pub fallback_stage: Option<u32>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 100,
+            time_budget: None,
+            fallback_stage: None,
+        }
     }
 }
 
 
 lazy_static! {
-    pub static ref GENERATORS: [&'static dyn LevelGenerator; 6] = {
+    pub static ref GENERATORS: [&'static dyn LevelGenerator; 9] = {
         [
             &Plain(),
             &Ring(),
@@ -47,6 +127,9 @@ lazy_static! {
             &Hourglass(),
             &DeceptivelySmall(),
             &TrickySquare(),
+            &Honeycomb(),
+            &Endless(),
+            &Collector(),
         ]
     };
 }
@@ -55,10 +138,15 @@ lazy_static! {
 pub struct Plain();
 
 impl LevelGenerator for Plain {
-    fn try_generate(&self, stage: u32, rng: &mut SmallRng) -> Result<Maze, GenerationError> {
+    fn try_generate(
+        &self, stage: u32, params: GenerationParams, rng: &mut SmallRng
+    ) -> Result<Maze, GenerationError> {
         let radius = 8 + stage as i32;
         let shape = make_circle(radius).collect();
-        let mut builder = MazeBuilder::new(shape, rng);
+        let mut builder = MazeBuilder::new(
+            shape, params, self.visibility_radius(stage), rng
+        );
+        builder.set_carving_algorithm(&LegacyGrowingTree());
         builder.generate_first_layer((0, 0));
         builder.set_finish_at_deepest_point(0);
         Ok(builder.into_maze())
@@ -73,18 +161,24 @@ impl LevelGenerator for Plain {
 pub struct Ring();
 
 impl LevelGenerator for Ring {
-    fn try_generate(&self, stage: u32, rng: &mut SmallRng) -> Result<Maze, GenerationError> {
+    fn try_generate(
+        &self, stage: u32, params: GenerationParams, rng: &mut SmallRng
+    ) -> Result<Maze, GenerationError> {
         let outer_radius = 17 + stage as i32 / 2;
         let inner_radius = outer_radius - 9;
         let depth = 1 + stage / 3;
         let shape: Vec<_> = make_ring(inner_radius, outer_radius).collect();
         let spawn = *shape.choose(rng).unwrap();
 
-        let mut builder = MazeBuilder::new(shape, rng);
+        let mut builder = MazeBuilder::new(
+            shape, params, self.visibility_radius(stage), rng
+        );
+        builder.set_carving_algorithm(&LegacyGrowingTree());
 
         let mut last = builder.generate_first_layer(spawn);
         for _ in 0..depth {
-            last = builder.add_layer_from_deepest_point(last)?;
+            last = builder.add_layer_from_deepest_point(last)
+                .map_err(|error| error.in_step("adding a layer"))?;
         }
         builder.set_finish_at_deepest_point(last);
 
@@ -105,17 +199,23 @@ impl LevelGenerator for Ring {
 pub struct Lemniscate();
 
 impl LevelGenerator for Lemniscate {
-    fn try_generate(&self, stage: u32, rng: &mut SmallRng) -> Result<Maze, GenerationError> {
+    fn try_generate(
+        &self, stage: u32, params: GenerationParams, rng: &mut SmallRng
+    ) -> Result<Maze, GenerationError> {
         let size = 20 + stage;
         let breadth = 3 + stage as i32 / 8;
         let mut shape: Vec<_> = make_lemniscate(size as f32, breadth).collect();
         shape.sort();
         let spawn = *shape.choose(rng).unwrap();
 
-        let mut builder = MazeBuilder::new(shape, rng);
+        let mut builder = MazeBuilder::new(
+            shape, params, self.visibility_radius(stage), rng
+        );
+        builder.set_carving_algorithm(&LegacyGrowingTree());
 
         let first = builder.generate_first_layer(spawn);
-        let last = builder.fork_to_two_layers(first)?.0;
+        let last = builder.fork_to_two_layers(first)
+            .map_err(|error| error.in_step("forking the first layer"))?.0;
         builder.set_finish_at_deepest_point(last);
 
         Ok(builder.into_maze())
@@ -132,18 +232,24 @@ impl LevelGenerator for Lemniscate {
 pub struct Hourglass();
 
 impl LevelGenerator for Hourglass {
-    fn try_generate(&self, stage: u32, rng: &mut SmallRng) -> Result<Maze, GenerationError> {
+    fn try_generate(
+        &self, stage: u32, params: GenerationParams, rng: &mut SmallRng
+    ) -> Result<Maze, GenerationError> {
         let radius = 10 + stage as i32;
         let depth = 1 + stage / 2;
         let shape = make_hourglass(radius).collect();
 
-        let mut builder = MazeBuilder::new(shape, rng);
+        let mut builder = MazeBuilder::new(
+            shape, params, self.visibility_radius(stage), rng
+        );
+        builder.set_carving_algorithm(&LegacyGrowingTree());
 
         let mut last = builder.generate_first_layer_from_multiple(
             &[(0, 0), (0, -1)]
         );
         for _ in 0..depth {
-            last = builder.fork_to_two_layers(last)?.1
+            last = builder.fork_to_two_layers(last)
+                .map_err(|error| error.in_step("forking a layer"))?.1
         }
         builder.set_finish_at_deepest_point(last);
 
@@ -162,14 +268,21 @@ impl LevelGenerator for Hourglass {
 pub struct DeceptivelySmall();
 
 impl LevelGenerator for DeceptivelySmall {
-    fn try_generate(&self, stage: u32, rng: &mut SmallRng) -> Result<Maze, GenerationError> {
-        let radius = visibility_radius() - 2;
+    fn try_generate(
+        &self, stage: u32, params: GenerationParams, rng: &mut SmallRng
+    ) -> Result<Maze, GenerationError> {
+        // The whole maze looks like it fits on the screen.
+        let radius = self.visibility_radius(stage) - 2;
         let depth = 1 + stage;
         let shape = make_circle(radius).collect();
-        let mut builder = MazeBuilder::new(shape, rng);
+        let mut builder = MazeBuilder::new(
+            shape, params, self.visibility_radius(stage), rng
+        );
+        builder.set_carving_algorithm(&LegacyGrowingTree());
         let mut last = builder.generate_first_layer((0, 0));
         for _ in 0..depth {
-            last = builder.add_layer_from_deepest_point(last)?;
+            last = builder.add_layer_from_deepest_point(last)
+                .map_err(|error| error.in_step("adding a layer"))?;
         }
         builder.set_finish_at_deepest_point(last);
         Ok(builder.into_maze())
@@ -186,21 +299,33 @@ impl LevelGenerator for DeceptivelySmall {
 pub struct TrickySquare();
 
 impl LevelGenerator for TrickySquare {
-    fn try_generate(&self, stage: u32, rng: &mut SmallRng) -> Result<Maze, GenerationError> {
+    fn try_generate(
+        &self, stage: u32, params: GenerationParams, rng: &mut SmallRng
+    ) -> Result<Maze, GenerationError> {
         let size = 12 + stage as i32;
         let depth = std::cmp::max(6, stage / 2);
         let shape = (-size..=size).cartesian_product(-size..=size).collect();
-        let mut builder = MazeBuilder::new(shape, rng);
+        let mut builder = MazeBuilder::new(
+            shape, params, self.visibility_radius(stage), rng
+        );
+        builder.set_carving_algorithm(&LegacyGrowingTree());
 
         let first = builder.generate_first_layer((0, size));
-        let (mut left, mut center, mut right) = builder.fork_to_three_layers(first)?;
+        let (mut left, mut center, mut right) = builder.fork_to_three_layers(first)
+            .map_err(|error| error.in_step("forking the first layer"))?;
         for _ in 0..depth {
-            left = builder.add_layer_from_deepest_point(left)?;
-            right = builder.add_layer_from_deepest_point(right)?;
+            let layers = builder.add_layers_from_deepest_points(&[left, right])
+                .map_err(|error| error.in_step("extending the side forks"))?;
+            left = layers[0];
+            right = layers[1];
         }
         if stage > 0 {
-            center = builder.add_layer_from_deepest_point(center)?;
+            center = builder.add_layer_from_deepest_point(center)
+                .map_err(|error| error.in_step("extending the central fork"))?;
         }
+        // Transitions don't keep players sticking to a wall from the finish,
+        // see `test_tricky_square_wall_followers`. Rejecting the mazes they
+        // solve would change the stages.
         builder.set_finish_at_deepest_point(center);
         Ok(builder.into_maze())
     }
@@ -212,3 +337,168 @@ impl LevelGenerator for TrickySquare {
     }
     fn recommended_length(&self) -> u32 { 3 }
 }
+
+
+pub struct Honeycomb();
+
+impl LevelGenerator for Honeycomb {
+    fn try_generate(
+        &self, stage: u32, params: GenerationParams, rng: &mut SmallRng
+    ) -> Result<Maze, GenerationError> {
+        let radius = 14 + stage as i32;
+        let depth = 1 + stage / 2;
+        let shape = make_hexagon(radius).collect();
+        let mut builder = MazeBuilder::new(
+            shape, params, self.visibility_radius(stage), rng
+        );
+        builder.set_topology(Topology::Hex);
+        builder.set_carving_algorithm(&RecursiveBacktracker());
+
+        let mut last = builder.generate_first_layer((0, 0));
+        for _ in 0..depth {
+            last = builder.add_layer_from_deepest_point(last)
+                .map_err(|error| error.in_step("adding a layer"))?;
+        }
+        builder.set_finish_at_deepest_point(last);
+        Ok(builder.into_maze())
+    }
+
+    fn id(&self) -> &'static str { "honeycomb" }
+    fn intro_text(&self) -> &'static str {
+        "Six ways to go now. Keys around \"S\" point to the neighbouring cells: \
+        \"W\" and \"E\" lead up, \"A\" and \"D\" lead sideways, \"Z\" and \"X\" lead down."
+    }
+    fn recommended_length(&self) -> u32 { 3 }
+}
+
+
+pub struct Collector();
+
+impl LevelGenerator for Collector {
+    fn try_generate(
+        &self, stage: u32, mut params: GenerationParams, rng: &mut SmallRng
+    ) -> Result<Maze, GenerationError> {
+        let radius = 16 + stage as i32;
+        let depth = stage / 2;
+        let shape: Vec<_> = make_circle(radius).collect();
+
+        // Halves of the circle get different textures so that it is harder
+        // to tell which one you are in.
+        let base = *params.base();
+        params.override_cells(
+            shape.iter().copied().filter(|&(x, _y)| x < 0),
+            CellParams { horizontal_weight: 2.5, ..base }
+        );
+        params.override_cells(
+            shape.iter().copied().filter(|&(x, _y)| x > 0),
+            CellParams { horizontal_weight: 0.4, ..base }
+        );
+
+        let mut builder = MazeBuilder::new(
+            shape, params, self.visibility_radius(stage), rng
+        );
+
+        let first = builder.generate_first_layer((0, 0));
+        let mut last = builder.fork_to_three_layers(first)
+            .map_err(|error| error.in_step("forking the first layer"))?.1;
+        for _ in 0..depth {
+            last = builder.add_layer_from_deepest_point(last)
+                .map_err(|error| error.in_step("extending the central fork"))?;
+        }
+        builder.set_finish_at_deepest_point(last);
+        // The side forks and the end of the central one hold an item each.
+        if builder.place_items_in_leaf_layers() < 3 {
+            return Err(GenerationError::Rejected("some leaf layers have no room for an item"));
+        }
+        Ok(builder.into_maze())
+    }
+
+    fn generation_params(&self, stage: u32) -> GenerationParams {
+        // Corridors get straighter and longer as stages progress.
+        GenerationParams::new(CellParams {
+            straightness: f64::min(0.25 * f64::from(stage), 2.0),
+            ..Default::default()
+        })
+    }
+
+    fn id(&self) -> &'static str { "collector" }
+    fn intro_text(&self) -> &'static str {
+        "The exit won't let you out empty-handed. Find every orb first."
+    }
+    fn recommended_length(&self) -> u32 { 3 }
+}
+
+
+pub struct Endless();
+
+impl LevelGenerator for Endless {
+    fn try_generate(
+        &self, stage: u32, _params: GenerationParams, rng: &mut SmallRng
+    ) -> Result<Maze, GenerationError> {
+        Ok(Maze::endless(ChunkedLayer::new(rng.gen()), (0, 0), self.visibility_radius(stage)))
+    }
+
+    fn id(&self) -> &'static str { "endless" }
+    fn intro_text(&self) -> &'static str {
+        "There is no way out this time. Wander around as long as you like."
+    }
+    fn recommended_length(&self) -> u32 { 1 }
+}
+
+#[test]
+fn test_retry_policy() {
+    use crate::layer::Layer;
+
+    /// Fails on every stage but the first one.
+    struct Fragile();
+
+    impl LevelGenerator for Fragile {
+        fn try_generate(
+            &self, stage: u32, _params: GenerationParams, _rng: &mut SmallRng
+        ) -> Result<Maze, GenerationError> {
+            if stage > 0 {
+                return Err(GenerationError::Rejected("too fragile"));
+            }
+            Ok(Maze::new(Layer::from_shape(&[(0, 0)]), (0, 0)))
+        }
+
+        fn id(&self) -> &'static str { "fragile" }
+        fn intro_text(&self) -> &'static str { "" }
+        fn recommended_length(&self) -> u32 { 1 }
+    }
+
+    let policy = RetryPolicy { max_attempts: 3, ..Default::default() };
+    let mut failures = 0;
+    let result = Fragile().generate_with(1, 0, &policy, &mut |_| failures += 1);
+    assert_eq!(failures, 3);
+    match result {
+        Err(GenerationError::GaveUp { attempts: 3, last_error: Some(error) }) =>
+            assert_eq!(*error, GenerationError::Rejected("too fragile")),
+        _ => panic!("generation didn't give up"),
+    }
+
+    let policy = RetryPolicy { fallback_stage: Some(0), ..policy };
+    assert!(Fragile().generate_with(1, 0, &policy, &mut |_| {}).is_ok());
+
+    let policy = RetryPolicy { time_budget: Some(Duration::from_secs(0)), ..policy };
+    assert!(matches!(
+        Fragile().generate_with(0, 0, &policy, &mut |_| {}),
+        Err(GenerationError::GaveUp { attempts: 0, last_error: None })
+    ));
+}
+
+#[test]
+fn test_collector_hints_collect_all_items() {
+    use crate::maze::MoveResult;
+
+    for stage in 0..Collector().recommended_length() {
+        let mut maze = Collector().generate(stage, u64::from(stage)).unwrap();
+        assert_eq!(maze.items_left(), 3);
+        let mut result = MoveResult::MovedToVisited;
+        while result != MoveResult::Finish {
+            result = maze.try_move_towards_finish();
+            assert_ne!(result, MoveResult::Obstacle, "hint got stuck on stage {}", stage);
+        }
+        assert_eq!(maze.items_left(), 0);
+    }
+}
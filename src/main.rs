@@ -13,12 +13,15 @@ mod utils;
 mod geometry;
 mod geometry_sets;
 mod layer;
+mod chunked_layer;
 mod maze;
 mod generation;
 mod build;
 mod visible_area;
 mod traversal;
 mod levels;
+mod calibration;
+mod seed_code;
 mod scene;
 mod screens;
 mod observers;
@@ -28,8 +31,11 @@ mod ui;
 use std::time::{Duration, SystemTime};
 
 use screens::menu::MenuScreen;
+use screens::replay::ReplayScreen;
+use scene::{Playback, Recording};
 use screens::ScreenManager;
 use fonts::Fonts;
+use maze::wall_follower::{self, Hand};
 
 pub const WINDOW_WIDTH: u32 = 1400;
 pub const WINDOW_HEIGHT: u32 = 900;
@@ -42,7 +48,235 @@ lazy_static! {
     };
 }
 
+/// Prints the structure of the mazes of the first stages of every level
+/// and whether sticking to a wall solves them, so levels can be compared
+/// without playing them.
+fn print_level_analysis() {
+    for (level, generator) in levels::GENERATORS.iter().enumerate() {
+        for stage in 0..generator.recommended_length() {
+            let code = seed_code::SeedCode::default_for(level, stage);
+            println!("{} ({}):", generator.id(), code);
+            let maze = match generator.generate(stage, code.seed) {
+                Ok(maze) => maze,
+                Err(error) => {
+                    println!("{}\n", error);
+                    continue;
+                },
+            };
+            print!("{}", maze::analysis::analyze(&maze));
+            print_storage_sizes(&maze);
+            if maze.items_left() > 0 {
+                println!("items: {}", maze.items_left());
+            }
+            if let Some(difficulty) = maze::difficulty::estimate(&maze) {
+                println!("difficulty: {:.0} ({:?})", difficulty.total(), difficulty);
+            }
+            for &hand in &[Hand::Left, Hand::Right] {
+                let outcome = wall_follower::follow_wall(
+                    &mut maze.clone(), hand, wall_follower::MAX_MOVES
+                );
+                let verdict = if outcome.is_defeat() { "defeated" } else { "solves it" };
+                println!("{:?} hand: {} ({:?})", hand, verdict, outcome);
+            }
+            println!();
+        }
+    }
+    print_algorithm_comparison();
+    print_escape_comparison();
+}
+
+/// Prints how much space the maze takes in both storage formats, checking
+/// that it loads back from them.
+fn print_storage_sizes(maze: &maze::Maze) {
+    use maze::storage;
+
+    let binary = storage::to_binary(maze);
+    if let Err(error) = storage::from_binary(&binary) {
+        println!("binary storage: {}", error);
+    }
+    match storage::to_ron(maze) {
+        Ok(text) => match storage::from_ron(&text) {
+            Ok(_) => println!("storage: {} bytes ({} bytes as RON)", binary.len(), text.len()),
+            Err(error) => println!("RON storage: {}", error),
+        },
+        Err(error) => println!("RON storage: {}", error),
+    }
+}
+
+/// Prints the difficulty of single-layer mazes of the same shape carved
+/// by every algorithm on every topology, so algorithms can be picked for
+/// new levels.
+fn print_algorithm_comparison() {
+    use rand::SeedableRng;
+    use rand::rngs::SmallRng;
+    use generation::*;
+    use geometry::Topology;
+
+    let algorithms: [(&str, &'static dyn CarvingAlgorithm<maze::LazyCellInfo>); 7] = [
+        ("growing tree", &GrowingTree()),
+        ("legacy growing tree", &LegacyGrowingTree()),
+        ("recursive backtracker", &RecursiveBacktracker()),
+        ("prim", &Prim()),
+        ("kruskal", &Kruskal()),
+        ("wilson", &Wilson()),
+        ("eller", &Eller()),
+    ];
+    let shapes: [(Vec<(i32, i32)>, Topology); 3] = [
+        (geometry_sets::make_circle(16).collect(), Topology::Square),
+        (geometry_sets::make_hexagon(16).collect(), Topology::Hex),
+        (geometry_sets::make_circle(20).collect(), Topology::Triangle),
+    ];
+    for (shape, topology) in &shapes {
+        println!("carving algorithms on {:?} cells:", topology);
+        for &(name, algorithm) in &algorithms {
+            let mut rng = SmallRng::seed_from_u64(0);
+            let mut builder = build::MazeBuilder::new(
+                shape.clone(), Default::default(),
+                visible_area::DEFAULT_VISIBILITY_RADIUS, &mut rng
+            );
+            builder.set_topology(*topology);
+            builder.set_carving_algorithm(algorithm);
+            builder.generate_first_layer((0, 0));
+            builder.set_finish_at_deepest_point(0);
+            match maze::difficulty::estimate(&builder.into_maze()) {
+                Some(difficulty) => println!("{}: difficulty {:.0}", name, difficulty.total()),
+                None => println!("{}: difficulty can't be estimated", name),
+            }
+        }
+    }
+}
+
+/// Prints the difficulty of two-layer mazes which differ only in how the
+/// escape of the transition is chosen, so escapes can be picked for new
+/// levels. The transition is placed at a junction on the way to the deepest
+/// point, and the difficulty is averaged over a few mazes.
+fn print_escape_comparison() {
+    use rand::SeedableRng;
+    use rand::rngs::SmallRng;
+    use build::{Escape, LayerOptions, MazeBuilder};
+
+    const MAZES: u64 = 8;
+    println!("escapes:");
+    for name in &["any", "nearest", "deepest", "further on the way"] {
+        let mut total = 0.0;
+        let mut estimated = 0;
+        for seed in 0..MAZES {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let mut builder = MazeBuilder::new(
+                geometry_sets::make_circle(16).collect(), Default::default(),
+                visible_area::DEFAULT_VISIBILITY_RADIUS, &mut rng
+            );
+            let first = builder.generate_first_layer((0, 0));
+            let info = builder.traversal_info(first);
+            let deepest = *info.coords.keys().max_by_key(|&coord| info.coords[coord].depth).unwrap();
+            let way: Vec<_> = traversal::get_path_to((0, 0), deepest, info).into_iter()
+                .scan((0, 0), |cell, dir| {
+                    *cell = *cell + dir;
+                    Some(*cell)
+                })
+                .collect();
+            // Escapes only differ if there are several ways ahead.
+            let branches = |&cell: &(i32, i32)| geometry::Topology::Square.directions(cell).iter()
+                .filter(|&&dir| {
+                    info.coords.get(&(cell + dir))
+                        .is_some_and(|next| next.came_from == Some(dir.opposite()))
+                })
+                .count();
+            let junction = match way[way.len() / 4..].iter().find(|cell| branches(cell) > 1) {
+                Some(&junction) => junction,
+                None => continue,
+            };
+            let escape = match *name {
+                "any" => Escape::Any,
+                "nearest" => Escape::Nearest,
+                "deepest" => Escape::Deepest,
+                _ => Escape::At(way[way.len() / 2]),
+            };
+            let region = geometry_sets::make_circle(3)
+                .map(|(x, y)| (x + junction.0, y + junction.1))
+                .collect::<std::collections::HashSet<_>>();
+            let options = LayerOptions { region: Some(region.into()), escape, params: None };
+            let second = match builder.add_layer_at(first, junction, options) {
+                Ok(second) => second,
+                Err(_) => continue,
+            };
+            builder.set_finish_at_deepest_point(second);
+            if let Some(difficulty) = maze::difficulty::estimate(&builder.into_maze()) {
+                total += difficulty.total();
+                estimated += 1;
+            }
+        }
+        match estimated {
+            0 => println!("{}: difficulty can't be estimated", name),
+            _ => println!(
+                "{}: difficulty {:.0} ({} mazes of {})",
+                name, total / estimated as f32, estimated, MAZES
+            ),
+        }
+    }
+}
+
+/// How much harder every stage should be than the previous one.
+const CALIBRATION_GROWTH: f32 = 0.2;
+
+/// Prints the generator stages and parameters which make the stages
+/// of every level climb a curve of difficulty starting from the first stage.
+fn print_calibration() {
+    for generator in levels::GENERATORS.iter() {
+        let first = match calibration::measure(*generator, 0, 0.0) {
+            Some(first) => first,
+            None => {
+                println!("{}: difficulty can't be estimated\n", generator.id());
+                continue;
+            },
+        };
+        let curve = calibration::TargetCurve { first, growth: CALIBRATION_GROWTH };
+        println!("{}:", generator.id());
+        let stages = calibration::calibrate(
+            generator.recommended_length() * 2, curve,
+            |stage, braid| calibration::measure(*generator, stage, braid)
+        );
+        for (stage, calibrated) in stages.iter().enumerate() {
+            println!(
+                "stage {}: generator stage {}, braid {:.1}, difficulty {:.0} (target {:.0})",
+                stage, calibrated.generator_stage, calibrated.braid,
+                calibrated.difficulty, calibrated.target
+            );
+        }
+        println!();
+    }
+}
+
+fn replay_path() -> Option<String> {
+    std::env::args().skip_while(|arg| arg != "--replay").nth(1)
+}
+
+fn load_playback(path: &std::path::Path) -> Result<Playback, Box<dyn std::error::Error>> {
+    Ok(Playback::new(Recording::load(path)?)?)
+}
+
 fn main() {
+    if std::env::args().any(|arg| arg == "--analyze") {
+        print_level_analysis();
+        return;
+    }
+    if std::env::args().any(|arg| arg == "--calibrate") {
+        print_calibration();
+        return;
+    }
+
+    // Recordings made with `--record` are played back with `--replay <file>`.
+    let initial_screen: Box<dyn screens::Screen> = match replay_path() {
+        Some(path) => match load_playback(std::path::Path::new(&path)) {
+            Ok(playback) => Box::new(ReplayScreen::new(playback)),
+            Err(error) => {
+                eprintln!("Failed loading the recording {}: {}", path, error);
+                return;
+            },
+        },
+        None => MenuScreen::create_initial(),
+    };
+
     let sdl_context: sdl2::Sdl = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
 
@@ -56,7 +290,7 @@ fn main() {
     let fonts = Fonts::new(&TTF);
 
     let mut manager = ScreenManager::new(
-        MenuScreen::create_initial(),
+        initial_screen,
         canvas,
         fonts,
     );
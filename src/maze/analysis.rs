@@ -0,0 +1,219 @@
+use std::collections::HashSet;
+
+use crate::layer::Layer;
+use crate::maze::{Maze, LazyCellInfo};
+
+
+/// The shortest way from the spawn point to the finish.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Solution {
+    /// The number of moves.
+    pub length: usize,
+    /// How many times the player goes from one layer to another.
+    pub layer_switches: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerReport {
+    pub cells: usize,
+    /// For every cell with a single passage, the number of cells between
+    /// it and the nearest junction, including the cell itself.
+    pub dead_end_lengths: Vec<usize>,
+    /// Cells with more than two passages.
+    pub junctions: usize,
+    /// Cells with exactly two passages.
+    pub corridors: usize,
+    /// Cells which can be reached from the spawn point, going through
+    /// transitions between layers.
+    pub reachable: usize,
+}
+
+impl LayerReport {
+    pub fn junction_density(&self) -> f32 {
+        self.junctions as f32 / self.cells as f32
+    }
+
+    /// Share of the cells with exactly two passages. Mazes with a high river
+    /// factor consist of long winding corridors, while mazes with a low one
+    /// branch all the time.
+    pub fn river_factor(&self) -> f32 {
+        self.corridors as f32 / self.cells as f32
+    }
+
+    /// Share of the cells which can be reached from the spawn point.
+    /// Transitions to other layers hide parts of a layer, so layers with
+    /// transitions are rarely covered entirely.
+    pub fn coverage(&self) -> f32 {
+        self.reachable as f32 / self.cells as f32
+    }
+}
+
+/// Structure of a maze, which allows comparing levels and stages
+/// without playing them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    /// `None` if the maze has no finish or the finish can't be reached.
+    pub solution: Option<Solution>,
+    pub layers: Vec<LayerReport>,
+}
+
+/// Analyzes the maze as it is right after generation. The player's moves
+/// don't change the result.
+pub fn analyze(maze: &Maze) -> Report {
+    let start = (maze.spawn_point.0, maze.spawn_point.1, 0);
+    let (came_from, _) = maze.search(start, |_| false);
+
+    let solution = maze.finish.filter(|finish| came_from.contains_key(finish)).map(|finish| {
+        let mut solution = Solution { length: 0, layer_switches: 0 };
+        let mut state = finish;
+        while let Some((prev, _)) = came_from[&state] {
+            solution.length += 1;
+            if prev.2 != state.2 {
+                solution.layer_switches += 1;
+            }
+            state = prev;
+        }
+        solution
+    });
+
+    let layers = maze.layers.iter().enumerate().map(|(index, maze_layer)| {
+        // Cells with transitions are never stood on in this layer, so they
+        // count as reached if the cells they lead to are.
+        let reached: HashSet<_> = came_from.keys()
+            .map(|&(x, y, _)| (x, y))
+            .filter(|&coord| {
+                let (x, y) = coord;
+                came_from.contains_key(&(x, y, maze.layer_after_entering(index, coord)))
+            })
+            .collect();
+        analyze_layer(&maze_layer.layer, &reached)
+    }).collect();
+
+    Report { solution, layers }
+}
+
+fn analyze_layer(layer: &Layer<LazyCellInfo>, reached: &HashSet<(i32, i32)>) -> LayerReport {
+    let passages = |coord| layer.directions(coord).iter()
+        .filter(|&&dir| layer.passable(coord, dir))
+        .count();
+
+    let mut report = LayerReport {
+        cells: 0,
+        dead_end_lengths: Vec::new(),
+        junctions: 0,
+        corridors: 0,
+        reachable: 0,
+    };
+    for coord in layer.cells() {
+        report.cells += 1;
+        if reached.contains(&coord) {
+            report.reachable += 1;
+        }
+        match passages(coord) {
+            0 => {},
+            1 => report.dead_end_lengths.push(dead_end_length(layer, coord)),
+            2 => report.corridors += 1,
+            _ => report.junctions += 1,
+        }
+    }
+    report
+}
+
+/// Walks from a cell with a single passage until a junction is found
+/// and returns the number of cells passed before it.
+fn dead_end_length(layer: &Layer<LazyCellInfo>, dead_end: (i32, i32)) -> usize {
+    let mut length = 0;
+    let mut prev = None;
+    let mut coord = dead_end;
+    loop {
+        let next: Vec<_> = layer.directions(coord).iter()
+            .filter(|&&dir| layer.passable(coord, dir))
+            .map(|&dir| coord + dir)
+            .filter(|&to| Some(to) != prev)
+            .collect();
+        if next.len() > 1 {
+            return length;
+        }
+        length += 1;
+        match next.first() {
+            Some(&to) => {
+                prev = Some(coord);
+                coord = to;
+            },
+            // The whole layer is a single corridor.
+            None => return length,
+        }
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.solution {
+            Some(solution) => writeln!(
+                f, "solution: {} moves, {} layer switches",
+                solution.length, solution.layer_switches
+            )?,
+            None => writeln!(f, "solution: none")?,
+        }
+        for (index, layer) in self.layers.iter().enumerate() {
+            let dead_ends = &layer.dead_end_lengths;
+            let average = dead_ends.iter().sum::<usize>() as f32 / dead_ends.len().max(1) as f32;
+            writeln!(
+                f,
+                "layer {}: {} cells, {} dead ends (average length {:.1}, longest {}), \
+                 junction density {:.3}, river factor {:.3}, coverage {:.1}%",
+                index, layer.cells, dead_ends.len(), average,
+                dead_ends.iter().max().unwrap_or(&0),
+                layer.junction_density(), layer.river_factor(), layer.coverage() * 100.0
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_analyze() {
+    use crate::geometry::Dir;
+    use crate::traversal;
+    use crate::visible_area::DEFAULT_VISIBILITY_RADIUS;
+
+    // A T-shaped layer.
+    let mut layer = Layer::from_shape(&[(0, 0), (1, 0), (2, 0), (1, 1), (1, 2)]);
+    layer.join((0, 0), Dir::RIGHT);
+    layer.join((1, 0), Dir::RIGHT);
+    layer.join((1, 0), Dir::DOWN);
+    layer.join((1, 1), Dir::DOWN);
+    let mut maze = Maze::new(layer, (0, 0));
+    maze.set_finish((1, 2, 0));
+    maze.try_move(Dir::RIGHT);
+
+    let report = analyze(&maze);
+    assert_eq!(report.solution, Some(Solution { length: 3, layer_switches: 0 }));
+    let layer = &report.layers[0];
+    let mut dead_end_lengths = layer.dead_end_lengths.clone();
+    dead_end_lengths.sort();
+    assert_eq!(dead_end_lengths, vec![1, 1, 2]);
+    assert_eq!((layer.junctions, layer.corridors), (1, 1));
+    assert_eq!(layer.coverage(), 1.0);
+
+    // A corridor whose middle is replaced by another layer.
+    let mut first = Layer::from_shape(&(0..=3).map(|i| (0, i)).collect::<Vec<_>>());
+    for i in 1..=3 {
+        first.join((0, i), Dir::UP);
+    }
+    let mut second = first.clone();
+    for i in 1..=3 {
+        *second.get_info_mut((0, i)).unwrap() = LazyCellInfo::Ref(0);
+    }
+    let mut maze = Maze::new(first, (0, 0));
+    let info = traversal::dfs(&second, (0, 2), Some(Dir::UP), DEFAULT_VISIBILITY_RADIUS);
+    let second_layer = maze.add_layer(second, info);
+    maze.add_transition((0, 1), Dir::DOWN, 0, 1);
+    maze.set_finish((0, 3, second_layer));
+
+    let report = analyze(&maze);
+    assert_eq!(report.solution, Some(Solution { length: 3, layer_switches: 1 }));
+    // The ends of the corridor can only be reached in one of the layers.
+    assert_eq!(report.layers[0].coverage(), 0.75);
+    assert_eq!(report.layers[1].coverage(), 0.75);
+}
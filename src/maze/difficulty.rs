@@ -0,0 +1,99 @@
+use crate::maze::Maze;
+use crate::maze::analysis::{self, Report};
+use crate::maze::wall_follower::{self, follow_wall, Hand};
+
+
+// All the parts of the difficulty are measured in moves of a player who
+// knows the way. The weights tell how many such moves a single feature
+// of the maze is worth. They are rough guesses made by playing.
+const LAYER_SWITCH_WEIGHT: f32 = 40.0;
+const DEAD_END_CELL_WEIGHT: f32 = 0.5;
+const WALL_FOLLOWER_DEFEAT_WEIGHT: f32 = 100.0;
+
+/// Estimated difficulty of a maze split into the parts it is made of.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Difficulty {
+    /// Length of the shortest way to the finish.
+    pub solution: f32,
+    /// Every transition on the way to the finish makes the player lose
+    /// track of the maze.
+    pub layer_switches: f32,
+    /// Dead ends which can be reached are where the player loses time.
+    pub dead_ends: f32,
+    /// Mazes which can't be solved by sticking to a wall make the player
+    /// actually think.
+    pub wall_followers: f32,
+}
+
+impl Difficulty {
+    pub fn total(&self) -> f32 {
+        self.solution + self.layer_switches + self.dead_ends + self.wall_followers
+    }
+}
+
+/// Estimates the difficulty of the maze as it is right after generation.
+/// The player's moves don't change the result.
+/// Returns `None` if the maze has no finish which can be reached.
+pub fn estimate(maze: &Maze) -> Option<Difficulty> {
+    let report = analysis::analyze(maze);
+    let solution = report.solution.as_ref()?;
+    let defeated_hands = [Hand::Left, Hand::Right].iter()
+        .filter(|&&hand| {
+            follow_wall(&mut at_start(maze), hand, wall_follower::MAX_MOVES).is_defeat()
+        })
+        .count();
+
+    Some(Difficulty {
+        solution: solution.length as f32,
+        layer_switches: solution.layer_switches as f32 * LAYER_SWITCH_WEIGHT,
+        dead_ends: dead_end_mass(&report) * DEAD_END_CELL_WEIGHT,
+        wall_followers: defeated_hands as f32 * WALL_FOLLOWER_DEFEAT_WEIGHT,
+    })
+}
+
+/// A copy of the maze with all the moves undone, so the player is back at
+/// the spawn point and the items are back in their cells.
+fn at_start(maze: &Maze) -> Maze {
+    let mut maze = maze.clone();
+    while !maze.history().is_empty() {
+        maze.undo();
+    }
+    maze
+}
+
+/// The number of cells in dead ends the player can get into. Parts of
+/// layers hidden by transitions can't be visited, so the dead ends of
+/// every layer are only counted in proportion to its coverage.
+fn dead_end_mass(report: &Report) -> f32 {
+    report.layers.iter()
+        .map(|layer| layer.dead_end_lengths.iter().sum::<usize>() as f32 * layer.coverage())
+        .sum()
+}
+
+#[test]
+fn test_estimate() {
+    use crate::geometry::Dir;
+    use crate::layer::Layer;
+
+    // A corridor with a dead end of two cells in the middle.
+    let mut layer = Layer::from_shape(&[(0, 0), (1, 0), (2, 0), (1, 1), (1, 2)]);
+    layer.join((0, 0), Dir::RIGHT);
+    layer.join((1, 0), Dir::RIGHT);
+    layer.join((1, 0), Dir::DOWN);
+    layer.join((1, 1), Dir::DOWN);
+    let mut maze = Maze::new(layer, (0, 0));
+    assert_eq!(estimate(&maze), None);
+
+    maze.set_finish((2, 0, 0));
+    let difficulty = estimate(&maze).unwrap();
+    assert_eq!(difficulty.solution, 2.0);
+    assert_eq!(difficulty.layer_switches, 0.0);
+    // The start and the finish are dead ends too.
+    assert_eq!(difficulty.dead_ends, 4.0 * DEAD_END_CELL_WEIGHT);
+    assert_eq!(difficulty.wall_followers, 0.0);
+
+    // Moves don't change the estimate.
+    maze.try_move(Dir::RIGHT);
+    maze.try_move(Dir::DOWN);
+    assert_eq!(estimate(&maze), Some(difficulty));
+}
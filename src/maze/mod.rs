@@ -0,0 +1,863 @@
+pub mod analysis;
+pub mod difficulty;
+pub mod storage;
+pub mod wall_follower;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::hash_map::Entry;
+
+use crate::layer::Layer;
+use crate::chunked_layer::ChunkedLayer;
+use crate::geometry::Dir;
+use crate::traversal;
+use crate::visible_area::DEFAULT_VISIBILITY_RADIUS;
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum CellInfo {
+    #[default]
+    Untouched,
+    Visited,
+    Finish,
+    /// An untouched cell with an item which must be collected
+    /// before the finish counts.
+    Item,
+}
+
+#[derive(Copy, Clone, serde::Serialize, serde::Deserialize)]
+pub enum LazyCellInfo {
+    Some(CellInfo),
+    Ref(usize),
+}
+
+impl Default for LazyCellInfo {
+    fn default() -> Self {
+        LazyCellInfo::Some(Default::default())
+    }
+}
+
+
+#[derive(Clone, Copy, serde::Serialize, serde::Deserialize)]
+struct Transition {
+    dest_layer: usize,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct MazeLayer {
+    pub layer: Layer<LazyCellInfo>,
+    transitions: HashMap<(i32, i32), Transition>,
+    pub info: traversal::Info,
+}
+
+/// Endless mazes have a single layer which covers only the chunks around
+/// the player and is rebuilt when the player moves to another chunk.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+struct EndlessSource {
+    chunks: ChunkedLayer,
+    center_chunk: (i32, i32),
+    /// Infos of the cells which are not covered by the layer at the moment.
+    stored_infos: HashMap<(i32, i32), CellInfo>,
+}
+
+/// A single move of the player, as recorded in the history of the maze.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Step {
+    pub dir: Dir,
+    /// The layer the move was made from.
+    pub layer: usize,
+    /// What the entered cell was before the move.
+    pub entered: CellInfo,
+}
+
+/// The current layer isn't serialized, it is rebuilt on load.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(remote = "Self")]
+pub struct Maze {
+    layers: Vec<MazeLayer>,
+    spawn_point: (i32, i32),
+    position: (i32, i32),
+    current_layer_index: usize,
+    // A copy of the current layer is made for speeding up rendering.
+    #[serde(skip)]
+    current_layer: Layer<CellInfo>,
+    path_from_start: Vec<Dir>,
+    path_from_finish: Vec<Dir>,
+    /// Every move made, unlike `path_from_start`, which only keeps
+    /// the way back.
+    history: Vec<Step>,
+    /// Undone moves, the last one is redone first.
+    undone: Vec<Step>,
+    finish: Option<(i32, i32, usize)>,
+    items_left: usize,
+    endless: Option<EndlessSource>,
+    visibility_radius: i32,
+}
+
+/// For every cell reached by `Maze::search`, the previous cell on the way
+/// along with the move made from it.
+type CameFrom = HashMap<(i32, i32, usize), Option<((i32, i32, usize), Dir)>>;
+
+#[derive(Debug, PartialEq)]
+pub enum MoveResult{
+    MovedToVisited,
+    MovedToUntouched,
+    PickedUp,
+    Obstacle,
+    /// Reaching the finish with items left counts as moving to a visited cell.
+    Finish,
+}
+
+impl Maze {
+    fn resolve_references(&self, layer: &Layer<LazyCellInfo>) -> Layer<CellInfo> {
+        layer.map(|&info, coord| {
+            match info {
+                LazyCellInfo::Some(info) => info,
+                LazyCellInfo::Ref(to_layer) => {
+                    match *self.layers[to_layer].layer.get_info(coord).unwrap() {
+                        LazyCellInfo::Some(info) => info,
+                        LazyCellInfo::Ref(_) => panic!("LazyCellInfo::Ref leads to another Ref")
+                    }
+                }
+            }
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn new(layer: Layer<LazyCellInfo>, spawn_point: (i32, i32)) -> Maze {
+        Self::with_visibility_radius(layer, spawn_point, DEFAULT_VISIBILITY_RADIUS)
+    }
+
+    /// Creates a maze in which the player sees `visibility_radius` cells
+    /// around. Layers added later must be traversed with the same radius.
+    pub fn with_visibility_radius(
+        layer: Layer<LazyCellInfo>,
+        spawn_point: (i32, i32),
+        visibility_radius: i32,
+    ) -> Maze {
+        let info = traversal::dfs(&layer, spawn_point, None, visibility_radius);
+        Self::with_traversal(layer, info, spawn_point, visibility_radius)
+    }
+
+    /// Same as `with_visibility_radius`, but the layer is already traversed
+    /// from `spawn_point`.
+    pub fn with_traversal(
+        layer: Layer<LazyCellInfo>,
+        info: traversal::Info,
+        spawn_point: (i32, i32),
+        visibility_radius: i32,
+    ) -> Maze {
+        let mut result = Maze {
+            layers: vec![MazeLayer{
+                layer,
+                transitions: HashMap::new(),
+                info,
+            }],
+            spawn_point,
+            position: spawn_point,
+            current_layer_index: 0,
+            current_layer: Default::default(),
+            path_from_start: Vec::new(),
+            path_from_finish: Vec::new(),
+            history: Vec::new(),
+            undone: Vec::new(),
+            finish: None,
+            items_left: 0,
+            endless: None,
+            visibility_radius,
+        };
+        result.current_layer = result.resolve_references(&result.layers[0].layer);
+        result.on_position_updated();
+        result
+    }
+
+    /// Creates a maze without borders and finish.
+    pub fn endless(
+        mut chunks: ChunkedLayer,
+        spawn_point: (i32, i32),
+        visibility_radius: i32,
+    ) -> Maze {
+        let center_chunk = ChunkedLayer::chunk_of(spawn_point);
+        let layer = chunks.window(center_chunk);
+        let mut result = Maze {
+            layers: vec![MazeLayer{
+                layer,
+                transitions: HashMap::new(),
+                // Layers of endless mazes are never used for building new ones.
+                info: Default::default(),
+            }],
+            spawn_point,
+            position: spawn_point,
+            current_layer_index: 0,
+            current_layer: Default::default(),
+            path_from_start: Vec::new(),
+            path_from_finish: Vec::new(),
+            history: Vec::new(),
+            undone: Vec::new(),
+            finish: None,
+            items_left: 0,
+            endless: Some(EndlessSource {
+                chunks,
+                center_chunk,
+                stored_infos: HashMap::new(),
+            }),
+            visibility_radius,
+        };
+        result.current_layer = result.resolve_references(&result.layers[0].layer);
+        result.on_position_updated();
+        result
+    }
+
+    /// Moves the window of an endless maze if the player entered another chunk.
+    fn update_endless_layer(&mut self) {
+        let endless = match self.endless.as_mut() {
+            Some(endless) => endless,
+            None => return,
+        };
+        let center_chunk = ChunkedLayer::chunk_of(self.position);
+        if center_chunk == endless.center_chunk {
+            return;
+        }
+
+        let old_layer = &self.layers[0].layer;
+        let mut layer: Layer<LazyCellInfo> = endless.chunks.window(center_chunk);
+        for cell in ChunkedLayer::window_cells(center_chunk) {
+            let info = match old_layer.get_info(cell) {
+                Some(&info) => info,
+                None => match endless.stored_infos.remove(&cell) {
+                    Some(info) => LazyCellInfo::Some(info),
+                    None => continue,
+                },
+            };
+            *layer.get_info_mut(cell).unwrap() = info;
+        }
+        for cell in ChunkedLayer::window_cells(endless.center_chunk) {
+            if !layer.has(cell) {
+                if let Some(&LazyCellInfo::Some(info)) = old_layer.get_info(cell) {
+                    if info != CellInfo::Untouched {
+                        endless.stored_infos.insert(cell, info);
+                    }
+                }
+            }
+        }
+
+        endless.center_chunk = center_chunk;
+        self.layers[0].layer = layer;
+        self.update_current_level();
+    }
+
+    /// Returns the index of the layer the player ends up in after entering
+    /// `coord` while being in the layer `layer_index`.
+    pub fn layer_after_entering(&self, layer_index: usize, coord: (i32, i32)) -> usize {
+        self.layers[layer_index].transitions.get(&coord).map_or(
+            layer_index,
+            |transition| transition.dest_layer
+        )
+    }
+
+    /// Returns what the cell the player is in was before.
+    fn on_position_updated(&mut self) -> CellInfo {
+        self.update_endless_layer();
+        let layer_index = self.layer_after_entering(self.current_layer_index, self.position);
+        if layer_index != self.current_layer_index {
+            self.current_layer_index = layer_index;
+            self.update_current_level();
+        }
+
+        let mut entered = CellInfo::Untouched;
+        self.modify_cell_info(
+            (self.position.0, self.position.1, self.current_layer_index),
+            |info| {
+                entered = *info;
+                if let CellInfo::Untouched | CellInfo::Item = *info {
+                    *info = CellInfo::Visited;
+                }
+            }
+        );
+        if entered == CellInfo::Item {
+            self.items_left -= 1;
+        }
+        entered
+    }
+
+    fn update_current_level(&mut self) {
+        self.current_layer = self.resolve_references(
+            &self.layers[self.current_layer_index].layer
+        );
+    }
+
+    fn update_path(path: &mut Vec<Dir>, dir: Dir) {
+        if let Some(&last) = path.last() {
+            if last == dir.opposite() {
+                path.pop();
+                return;
+            }
+        }
+        path.push(dir);
+    }
+
+    fn do_move(&mut self, dir: Dir) -> CellInfo {
+        let layer = self.current_layer_index;
+        self.position = self.position + dir;
+        Self::update_path(&mut self.path_from_start, dir);
+        Self::update_path(&mut self.path_from_finish, dir);
+        let entered = self.on_position_updated();
+        self.history.push(Step { dir, layer, entered });
+        entered
+    }
+
+    pub fn try_move(&mut self, dir: Dir) -> MoveResult {
+        let result = self.make_move(dir);
+        if result != MoveResult::Obstacle {
+            self.undone.clear();
+        }
+        result
+    }
+
+    fn make_move(&mut self, dir: Dir) -> MoveResult {
+        if self.current_layer.passable(self.position, dir) {
+            match self.do_move(dir) {
+                CellInfo::Finish if self.items_left == 0 => MoveResult::Finish,
+                CellInfo::Finish | CellInfo::Visited => MoveResult::MovedToVisited,
+                CellInfo::Untouched => MoveResult::MovedToUntouched,
+                CellInfo::Item => MoveResult::PickedUp,
+            }
+        } else {
+            MoveResult::Obstacle
+        }
+    }
+
+    /// Takes the last move back, restoring the position, the layer
+    /// and the left cell along with its item.
+    pub fn undo(&mut self) -> MoveResult {
+        let step = match self.history.pop() {
+            Some(step) => step,
+            None => return MoveResult::Obstacle,
+        };
+        if let CellInfo::Untouched | CellInfo::Item = step.entered {
+            self.modify_cell_info(
+                (self.position.0, self.position.1, self.current_layer_index),
+                |info| *info = step.entered
+            );
+        }
+        if step.entered == CellInfo::Item {
+            self.items_left += 1;
+        }
+        let dir = step.dir.opposite();
+        self.position = self.position + dir;
+        Self::update_path(&mut self.path_from_start, dir);
+        Self::update_path(&mut self.path_from_finish, dir);
+        self.update_endless_layer();
+        if step.layer != self.current_layer_index {
+            self.current_layer_index = step.layer;
+            self.update_current_level();
+        }
+        self.undone.push(step);
+        MoveResult::MovedToVisited
+    }
+
+    /// Makes the last undone move again.
+    pub fn redo(&mut self) -> MoveResult {
+        match self.undone.pop() {
+            Some(step) => self.make_move(step.dir),
+            None => MoveResult::Obstacle,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn history(&self) -> &[Step] {
+        &self.history
+    }
+
+    pub fn try_move_towards_start(&mut self) -> MoveResult {
+        if !self.path_from_start.is_empty() {
+            self.try_move(self.path_from_start.last().unwrap().opposite())
+        } else {
+            MoveResult::Obstacle
+        }
+    }
+
+    pub fn try_move_towards_finish(&mut self) -> MoveResult {
+        if !self.path_from_finish.is_empty() {
+            self.try_move(self.path_from_finish.last().unwrap().opposite())
+        } else {
+            MoveResult::Obstacle
+        }
+    }
+
+    pub fn current_layer(&self) -> &Layer<CellInfo> {
+        &self.current_layer
+    }
+
+    pub fn position(&self) -> (i32, i32) {
+        self.position
+    }
+
+    pub fn visibility_radius(&self) -> i32 {
+        self.visibility_radius
+    }
+
+    pub fn current_layer_info(&self) -> &traversal::Info {
+        &self.layers[self.current_layer_index].info
+    }
+
+    pub fn maze_layer(&self, i: usize) -> &MazeLayer {
+        &self.layers[i]
+    }
+
+    pub fn set_finish(&mut self, pos: (i32, i32, usize)) {
+        self.modify_cell_info(pos, |info| *info = CellInfo::Finish);
+        assert!(self.finish.is_none(), "Finish is already set");
+        self.finish = Some(pos);
+        self.update_path_from_finish();
+    }
+
+    /// Puts an item into an untouched cell. Cells shared with other layers
+    /// get the item in all of them.
+    pub fn add_item(&mut self, pos: (i32, i32, usize)) {
+        self.modify_cell_info(pos, |info| {
+            assert!(*info == CellInfo::Untouched, "Items can only be put into untouched cells");
+            *info = CellInfo::Item;
+        });
+        self.items_left += 1;
+        self.update_path_from_finish();
+    }
+
+    pub fn items_left(&self) -> usize {
+        self.items_left
+    }
+
+    /// Returns the info of the cell, looking into the layer which owns it.
+    pub fn cell_info(&self, (x, y, z): (i32, i32, usize)) -> Option<CellInfo> {
+        let owner = self.layers.get(z)?.layer.get_info((x, y))?;
+        let owner = match *owner {
+            LazyCellInfo::Some(info) => return Some(info),
+            LazyCellInfo::Ref(owner) => owner,
+        };
+        match *self.layers[owner].layer.get_info((x, y))? {
+            LazyCellInfo::Some(info) => Some(info),
+            LazyCellInfo::Ref(_) => panic!("LazyCellInfo::Ref leads to another Ref")
+        }
+    }
+
+    /// The same cell in the layer which owns its info.
+    fn cell_owner(&self, (x, y, z): (i32, i32, usize)) -> (i32, i32, usize) {
+        match *self.layers[z].layer.get_info((x, y)).unwrap() {
+            LazyCellInfo::Some(_) => (x, y, z),
+            LazyCellInfo::Ref(owner) => (x, y, owner),
+        }
+    }
+
+    /// Layers no other layer was added from. Layers are added after
+    /// the ones they are entered from, so transitions of leaf layers
+    /// only lead to layers with lower indices.
+    pub fn leaf_layers(&self) -> Vec<usize> {
+        (0..self.layers.len())
+            .filter(|&index| {
+                self.layers[index].transitions.values()
+                    .all(|transition| transition.dest_layer < index)
+            })
+            .collect()
+    }
+
+    fn mut_lazy_cell_info(&mut self, (x, y, z): (i32, i32, usize)) -> Option<&mut LazyCellInfo> {
+        self.layers.get_mut(z)?.layer.get_info_mut((x, y))
+    }
+
+    pub fn modify_cell_info(
+        &mut self, (x, y, z): (i32, i32, usize),
+        modify: impl FnOnce(&mut CellInfo)
+    ) {
+        let owner = match *self.mut_lazy_cell_info((x, y, z)).unwrap() {
+            LazyCellInfo::Some(_) => z,
+            LazyCellInfo::Ref(to) => to,
+        };
+        let info = match self.mut_lazy_cell_info((x, y, owner)).unwrap() {
+            LazyCellInfo::Some(ref mut info) => {
+                modify(info);
+                *info
+            },
+            LazyCellInfo::Ref(_) => panic!("LazyCellInfo::Ref leads to another Ref")
+        };
+
+        // Only a single cell of the current layer may need an update.
+        let shown = match *self.layers[self.current_layer_index].layer.get_info((x, y)).unwrap() {
+            LazyCellInfo::Some(_) => self.current_layer_index,
+            LazyCellInfo::Ref(to) => to,
+        };
+        if shown == owner {
+            *self.current_layer.get_info_mut((x, y)).unwrap() = info;
+        }
+    }
+
+    pub fn add_layer(
+        &mut self,
+        layer: Layer<LazyCellInfo>, info: traversal::Info,
+    ) -> usize {
+        self.layers.push(MazeLayer{
+            layer,
+            transitions: HashMap::new(),
+            info,
+        });
+        self.layers.len() - 1
+    }
+
+    /// Makes going from `coord` in `dir` lead from one layer to another.
+    ///
+    /// A layer may be entered from several layers, each through its own
+    /// transition, and going back through a transition leads to the layer
+    /// it was entered from.
+    pub fn add_transition(&mut self, coord: (i32, i32), dir: Dir, from_index: usize, to_index: usize) {
+        let from = &mut self.layers[from_index];
+        assert!(from.layer.passable(coord, dir));
+        let previous = from.transitions.insert(coord + dir, Transition{dest_layer: to_index});
+        assert!(previous.is_none(), "Transitions overlap");
+
+        let to = &mut self.layers[to_index];
+        assert!(to.layer.passable(coord, dir));
+        let previous = to.transitions.insert(coord, Transition{dest_layer: from_index});
+        assert!(previous.is_none(), "Transitions overlap");
+    }
+
+    /// Searches for the shortest ways from `start` to the other cells of
+    /// the maze, stopping at the first cell for which `stop_at` is true.
+    ///
+    /// Layers can contain loops and the player can walk in circles, so the
+    /// search is done over all the layers taking transitions into account.
+    /// For every reached cell returns the previous one on the way along with
+    /// the move made from it. The cell the search stopped at is returned too.
+    fn search(
+        &self,
+        start: (i32, i32, usize),
+        stop_at: impl Fn((i32, i32, usize)) -> bool,
+    ) -> (CameFrom, Option<(i32, i32, usize)>) {
+        let mut came_from = HashMap::new();
+        came_from.insert(start, None);
+        let mut queue = VecDeque::new();
+        queue.push_back(start);
+        while let Some(state) = queue.pop_front() {
+            if stop_at(state) {
+                return (came_from, Some(state));
+            }
+            let (x, y, layer_index) = state;
+            for &dir in self.layers[layer_index].layer.directions((x, y)) {
+                if !self.layers[layer_index].layer.passable((x, y), dir) {
+                    continue;
+                }
+                let pos = (x, y) + dir;
+                let next = (pos.0, pos.1, self.layer_after_entering(layer_index, pos));
+                if let Entry::Vacant(entry) = came_from.entry(next) {
+                    entry.insert(Some((state, dir)));
+                    queue.push_back(next);
+                }
+            }
+        }
+        (came_from, None)
+    }
+
+    /// Finds the way from the current position to the finish going through
+    /// the cells with items, picking the nearest of the remaining items
+    /// every time.
+    pub fn update_path_from_finish(&mut self) {
+        self.path_from_finish.clear();
+        let finish = match self.finish {
+            Some(finish) => finish,
+            None => return,
+        };
+
+        let mut start = (self.position.0, self.position.1, self.current_layer_index);
+        // Cells shared between layers are told apart by the layers owning them.
+        let mut planned_items = HashSet::new();
+        let mut moves = Vec::new();
+        loop {
+            let collected = planned_items.len() == self.items_left;
+            let (came_from, reached) = self.search(start, |state| if collected {
+                state == finish
+            } else {
+                self.cell_info(state) == Some(CellInfo::Item)
+                    && !planned_items.contains(&self.cell_owner(state))
+            });
+            let reached = match reached {
+                Some(reached) => reached,
+                None => return,
+            };
+
+            let leg_start = moves.len();
+            let mut state = reached;
+            while let Some((prev, dir)) = came_from[&state] {
+                moves.push(dir);
+                state = prev;
+            }
+            moves[leg_start..].reverse();
+            if collected {
+                break;
+            }
+            planned_items.insert(self.cell_owner(reached));
+            start = reached;
+        }
+        self.path_from_finish = moves.iter().rev().map(|dir| dir.opposite()).collect();
+    }
+}
+
+#[test]
+fn test_path_from_finish_with_loops() {
+    use itertools::Itertools;
+
+    let mut layer = Layer::from_shape(
+        &(0..3).cartesian_product(0..2).collect::<Vec<_>>()
+    );
+    for x in 0..3 {
+        layer.join((x, 0), Dir::DOWN);
+    }
+    for y in 0..2 {
+        layer.join((0, y), Dir::RIGHT);
+        layer.join((1, y), Dir::RIGHT);
+    }
+
+    let mut maze = Maze::new(layer, (0, 0));
+    maze.set_finish((2, 0, 0));
+    assert_eq!(maze.try_move(Dir::DOWN), MoveResult::MovedToUntouched);
+    assert_eq!(maze.try_move(Dir::RIGHT), MoveResult::MovedToUntouched);
+    assert_eq!(maze.try_move(Dir::UP), MoveResult::MovedToUntouched);
+    assert_eq!(maze.path_from_finish.len(), 5);
+
+    maze.update_path_from_finish();
+    assert_eq!(maze.path_from_finish, vec![Dir::LEFT]);
+    assert_eq!(maze.try_move_towards_finish(), MoveResult::Finish);
+}
+
+#[test]
+fn test_maze() {
+    let mut first = Layer::from_shape(
+        &(0..=3).map(|i| (0, i)).collect::<Vec<_>>()
+    );
+    for i in 1..=3 {
+        first.join((0, i), Dir::UP);
+    }
+    let mut second = first.clone();
+    for i in 1..=3 {
+        *second.get_info_mut((0, i)).unwrap() = LazyCellInfo::Ref(0);
+    }
+
+    let mut maze = Maze::new(first, (0, 0));
+    let info = traversal::dfs(&second, (0, 2), Some(Dir::UP), DEFAULT_VISIBILITY_RADIUS);
+    let second_layer = maze.add_layer(second, info);
+    maze.add_transition((0, 1), Dir::DOWN, 0, 1);
+    maze.set_finish((0, 3, second_layer));
+
+    assert_eq!(maze.try_move(Dir::RIGHT), MoveResult::Obstacle);
+    assert_eq!(maze.try_move(Dir::DOWN), MoveResult::MovedToUntouched);
+    assert_eq!(maze.position, (0, 1));
+    assert_eq!(maze.current_layer_index, 0);
+    assert!(!maze.current_layer.has((2, 2)));
+
+    assert_eq!(maze.try_move(Dir::DOWN), MoveResult::MovedToUntouched);
+    assert_eq!(maze.position, (0, 2));
+    assert_eq!(maze.current_layer_index, 1);
+
+    assert_eq!(maze.try_move(Dir::DOWN), MoveResult::Finish);
+    assert_eq!(maze.position, (0, 3));
+    assert_eq!(maze.current_layer_index, 1);
+
+    assert_eq!(maze.try_move(Dir::UP), MoveResult::MovedToVisited);
+    assert_eq!(maze.position, (0, 2));
+    assert_eq!(maze.current_layer_index, 1);
+
+    assert_eq!(maze.try_move(Dir::UP), MoveResult::MovedToVisited);
+    assert_eq!(maze.position, (0, 1));
+    assert_eq!(maze.current_layer_index, 0);
+
+    assert_eq!(maze.try_move(Dir::UP), MoveResult::MovedToVisited);
+    assert_eq!(maze.position, (0, 0));
+    assert_eq!(maze.current_layer_index, 0);
+    assert_eq!(maze.try_move(Dir::UP), MoveResult::Obstacle);
+}
+
+#[test]
+fn test_layer_with_two_parents() {
+    use itertools::Itertools;
+
+    // A ring around (1, 1). Going right from the spawn point leads to the
+    // last layer directly, going down leads to it through another layer.
+    let shape: Vec<_> = (0..3).cartesian_product(0..3).filter(|&c| c != (1, 1)).collect();
+    let mut layer = Layer::from_shape(&shape);
+    for i in 0..2 {
+        layer.join((i, 0), Dir::RIGHT);
+        layer.join((i, 2), Dir::RIGHT);
+        layer.join((0, i), Dir::DOWN);
+        layer.join((2, i), Dir::DOWN);
+    }
+    // The cells the player comes from are shared with the previous layers.
+    let mut side = layer.clone();
+    *side.get_info_mut((0, 0)).unwrap() = LazyCellInfo::Ref(0);
+    let mut shared = side.clone();
+    for i in 1..=2 {
+        *shared.get_info_mut((0, i)).unwrap() = LazyCellInfo::Ref(1);
+    }
+
+    let mut maze = Maze::new(layer, (0, 0));
+    let info = traversal::dfs(&side, (0, 0), Some(Dir::DOWN), DEFAULT_VISIBILITY_RADIUS);
+    let side = maze.add_layer(side, info);
+    let info = traversal::dfs(&shared, (0, 0), Some(Dir::RIGHT), DEFAULT_VISIBILITY_RADIUS);
+    let shared = maze.add_layer(shared, info);
+    maze.add_transition((0, 0), Dir::RIGHT, 0, shared);
+    maze.add_transition((0, 0), Dir::DOWN, 0, side);
+    maze.add_transition((0, 2), Dir::RIGHT, side, shared);
+    maze.set_finish((2, 1, shared));
+    assert_eq!(maze.path_from_finish.len(), 3);
+
+    for &dir in &[Dir::DOWN, Dir::DOWN, Dir::RIGHT] {
+        assert_eq!(maze.try_move(dir), MoveResult::MovedToUntouched);
+    }
+    assert_eq!(maze.current_layer_index, shared);
+    maze.update_path_from_finish();
+    assert_eq!(maze.path_from_finish.len(), 2);
+
+    // Going back leads to the layer the shared one was entered from.
+    assert_eq!(maze.try_move(Dir::LEFT), MoveResult::MovedToVisited);
+    assert_eq!(maze.current_layer_index, side);
+    maze.try_move(Dir::RIGHT);
+    assert_eq!(maze.current_layer_index, shared);
+    assert_eq!(maze.try_move(Dir::RIGHT), MoveResult::MovedToUntouched);
+    assert_eq!(maze.try_move(Dir::UP), MoveResult::Finish);
+}
+
+#[test]
+fn test_undo_redo() {
+    // A corridor whose last two cells are in another layer.
+    let mut first = Layer::from_shape(&(0..=3).map(|i| (0, i)).collect::<Vec<_>>());
+    for i in 1..=3 {
+        first.join((0, i), Dir::UP);
+    }
+    let mut second = first.clone();
+    *second.get_info_mut((0, 1)).unwrap() = LazyCellInfo::Ref(0);
+    let mut maze = Maze::new(first, (0, 0));
+    let info = traversal::dfs(&second, (0, 2), Some(Dir::UP), DEFAULT_VISIBILITY_RADIUS);
+    let second_layer = maze.add_layer(second, info);
+    maze.add_transition((0, 1), Dir::DOWN, 0, second_layer);
+
+    for &dir in &[Dir::DOWN, Dir::DOWN, Dir::UP, Dir::DOWN] {
+        maze.try_move(dir);
+    }
+    assert_eq!(maze.history().len(), 4);
+    assert_eq!(maze.path_from_start, vec![Dir::DOWN, Dir::DOWN]);
+    assert_eq!(maze.history()[1], Step { dir: Dir::DOWN, layer: 0, entered: CellInfo::Untouched });
+    assert_eq!(maze.history()[3], Step { dir: Dir::DOWN, layer: 0, entered: CellInfo::Visited });
+
+    assert_eq!(maze.undo(), MoveResult::MovedToVisited);
+    assert_eq!(maze.undo(), MoveResult::MovedToVisited);
+    assert_eq!((maze.position, maze.current_layer_index), ((0, 2), second_layer));
+    assert_eq!(maze.undo(), MoveResult::MovedToVisited);
+    assert_eq!((maze.position, maze.current_layer_index), ((0, 1), 0));
+    // The cell the player went back to stays visited, while the cell entered
+    // by the undone move is untouched again.
+    assert!(*maze.current_layer.get_info((0, 1)).unwrap() == CellInfo::Visited);
+    assert!(matches!(
+        maze.layers[second_layer].layer.get_info((0, 2)),
+        Some(LazyCellInfo::Some(CellInfo::Untouched))
+    ));
+
+    assert_eq!(maze.redo(), MoveResult::MovedToUntouched);
+    assert_eq!((maze.position, maze.current_layer_index), ((0, 2), second_layer));
+    assert_eq!(maze.try_move(Dir::DOWN), MoveResult::MovedToUntouched);
+    // New moves make the undone ones impossible to redo.
+    assert_eq!(maze.redo(), MoveResult::Obstacle);
+    assert_eq!(maze.path_from_start, vec![Dir::DOWN, Dir::DOWN, Dir::DOWN]);
+    assert_eq!(maze.undo(), MoveResult::MovedToVisited);
+    assert_eq!(maze.undo(), MoveResult::MovedToVisited);
+    assert_eq!(maze.undo(), MoveResult::MovedToVisited);
+    assert_eq!(maze.undo(), MoveResult::Obstacle);
+    assert_eq!(maze.position, (0, 0));
+    assert!(maze.path_from_start.is_empty());
+}
+
+#[test]
+fn test_items() {
+    // A T-shaped layer with an item at the end of the stem.
+    let mut layer = Layer::from_shape(&[(0, 0), (1, 0), (2, 0), (1, 1), (1, 2)]);
+    layer.join((0, 0), Dir::RIGHT);
+    layer.join((1, 0), Dir::RIGHT);
+    layer.join((1, 0), Dir::DOWN);
+    layer.join((1, 1), Dir::DOWN);
+    let mut maze = Maze::new(layer, (0, 0));
+    maze.set_finish((2, 0, 0));
+    maze.add_item((1, 2, 0));
+    // The way to the finish goes through the item.
+    assert_eq!(maze.path_from_finish.len(), 6);
+
+    assert_eq!(maze.try_move(Dir::RIGHT), MoveResult::MovedToUntouched);
+    assert_eq!(maze.try_move(Dir::RIGHT), MoveResult::MovedToVisited);
+    assert_eq!(maze.items_left(), 1);
+    maze.try_move(Dir::LEFT);
+    maze.try_move(Dir::DOWN);
+    assert_eq!(maze.try_move(Dir::DOWN), MoveResult::PickedUp);
+    assert_eq!(maze.items_left(), 0);
+    assert_eq!(maze.undo(), MoveResult::MovedToVisited);
+    assert_eq!(maze.cell_info((1, 2, 0)), Some(CellInfo::Item));
+    assert_eq!(maze.redo(), MoveResult::PickedUp);
+    for &dir in &[Dir::UP, Dir::UP] {
+        maze.try_move(dir);
+    }
+    assert_eq!(maze.try_move(Dir::RIGHT), MoveResult::Finish);
+
+    // An item in a cell the second layer shares with the first one.
+    let mut first = Layer::from_shape(&(0..=3).map(|i| (0, i)).collect::<Vec<_>>());
+    for i in 1..=3 {
+        first.join((0, i), Dir::UP);
+    }
+    let mut second = first.clone();
+    for i in 1..=3 {
+        *second.get_info_mut((0, i)).unwrap() = LazyCellInfo::Ref(0);
+    }
+    let mut maze = Maze::new(first, (0, 0));
+    let info = traversal::dfs(&second, (0, 2), Some(Dir::UP), DEFAULT_VISIBILITY_RADIUS);
+    let second_layer = maze.add_layer(second, info);
+    maze.add_transition((0, 1), Dir::DOWN, 0, second_layer);
+    maze.add_item((0, 3, second_layer));
+    assert_eq!(maze.cell_info((0, 3, 0)), Some(CellInfo::Item));
+    assert_eq!(maze.leaf_layers(), vec![second_layer]);
+
+    maze.try_move(Dir::DOWN);
+    maze.try_move(Dir::DOWN);
+    assert_eq!(maze.current_layer_index, second_layer);
+    assert!(*maze.current_layer.get_info((0, 3)).unwrap() == CellInfo::Item);
+    assert_eq!(maze.try_move(Dir::DOWN), MoveResult::PickedUp);
+    assert_eq!(maze.cell_info((0, 3, 0)), Some(CellInfo::Visited));
+}
+
+#[test]
+fn test_legacy_levels_are_stable() {
+    use crate::levels::GENERATORS;
+
+    let mut fingerprint: u64 = 0;
+    // Every level of the original implementation but Hourglass, whose mazes
+    // differ from run to run even there.
+    for &(level, stage) in &[
+        (0, 0), (0, 3), (1, 0), (1, 1), (1, 3), (2, 0), (2, 1), (2, 2),
+        (4, 0), (4, 1), (4, 2), (5, 0), (5, 1)
+    ] {
+        let maze = GENERATORS[level].generate(stage, u64::from(stage)).unwrap();
+        for maze_layer in &maze.layers {
+            // Only the reachable part of a layer is played.
+            let mut cells: Vec<_> = maze_layer.info.coords.keys().copied().collect();
+            cells.sort();
+            for &(x, y) in &cells {
+                for (k, &dir) in [Dir::RIGHT, Dir::DOWN].iter().enumerate() {
+                    if maze_layer.layer.passable((x, y), dir) {
+                        let value = (x + 100) * 1000 + (y + 100) * 2 + k as i32;
+                        fingerprint = fingerprint.wrapping_mul(31).wrapping_add(value as u64);
+                    }
+                }
+            }
+            let mut transitions: Vec<_> = maze_layer.transitions.iter()
+                .map(|(&(x, y), transition)| (x, y, transition.dest_layer))
+                .collect();
+            transitions.sort();
+            for (x, y, dest_layer) in transitions {
+                let value = ((x + 100) * 1000 + (y + 100)) as u64 * 100 + dest_layer as u64;
+                fingerprint = fingerprint.wrapping_mul(31).wrapping_add(value);
+            }
+        }
+    }
+    // Computed with the original implementation.
+    assert_eq!(fingerprint, 13317053752247303516);
+}
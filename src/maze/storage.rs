@@ -0,0 +1,185 @@
+use bincode::Options;
+use serde::de::Error;
+
+use crate::maze::{Maze, CellInfo, LazyCellInfo};
+
+
+/// Binary mazes start with these bytes followed by the format version.
+const MAGIC: &[u8; 4] = b"AMZM";
+/// Must be increased whenever the serialized structure of `Maze` changes.
+const BINARY_VERSION: u8 = 3;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Ron(ron::de::Error),
+    Binary(bincode::Error),
+    /// The data doesn't start with the binary header.
+    NotAMaze,
+    UnsupportedVersion(u8),
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Ron(error) => write!(f, "invalid maze: {}", error),
+            LoadError::Binary(error) => write!(f, "invalid maze: {}", error),
+            LoadError::NotAMaze => write!(f, "not a maze"),
+            LoadError::UnsupportedVersion(version) =>
+                write!(f, "unsupported maze format version {}", version),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl serde::Serialize for Maze {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Self::serialize(self, serializer)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Maze {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut maze = Self::deserialize(deserializer)?;
+        validate(&maze).map_err(D::Error::custom)?;
+        maze.update_current_level();
+        Ok(maze)
+    }
+}
+
+/// Checks the links between layers and the state of the player, which
+/// the rest of the code relies on.
+fn validate(maze: &Maze) -> Result<(), &'static str> {
+    let layer_count = maze.layers.len();
+    if maze.current_layer_index >= layer_count {
+        return Err("current layer doesn't exist");
+    }
+    if maze.layers[maze.current_layer_index].layer.get_info(maze.position).is_none() {
+        return Err("position is outside the current layer");
+    }
+    // The window of an endless maze moves away from the spawn point.
+    if maze.endless.is_none() && maze.layers[0].layer.get_info(maze.spawn_point).is_none() {
+        return Err("spawn point is outside the first layer");
+    }
+    if maze.finish.is_some_and(|(_, _, layer)| layer >= layer_count) {
+        return Err("finish is in a layer which doesn't exist");
+    }
+    if maze.history.iter().chain(&maze.undone).any(|step| step.layer >= layer_count) {
+        return Err("move is made from a layer which doesn't exist");
+    }
+    let mut items = 0;
+    for maze_layer in &maze.layers {
+        if maze_layer.transitions.values().any(|transition| transition.dest_layer >= layer_count) {
+            return Err("transition leads to a layer which doesn't exist");
+        }
+        for coord in maze_layer.layer.cells() {
+            match *maze_layer.layer.get_info(coord).unwrap() {
+                LazyCellInfo::Ref(owner) => {
+                    let owner_info = maze.layers.get(owner)
+                        .and_then(|owner| owner.layer.get_info(coord));
+                    if !matches!(owner_info, Some(LazyCellInfo::Some(_))) {
+                        return Err("cell refers to a layer which doesn't have it");
+                    }
+                },
+                // Shared items are only counted in the layer owning them.
+                LazyCellInfo::Some(CellInfo::Item) => items += 1,
+                LazyCellInfo::Some(_) => (),
+            }
+        }
+    }
+    if items != maze.items_left {
+        return Err("number of items left doesn't match the items in the maze");
+    }
+    Ok(())
+}
+
+/// Readable format for looking into mazes and attaching them to bug reports.
+pub fn to_ron(maze: &Maze) -> Result<String, ron::ser::Error> {
+    ron::ser::to_string_pretty(maze, ron::ser::PrettyConfig::default())
+}
+
+/// Loads mazes written by `to_ron`, rejecting inconsistent ones.
+pub fn from_ron(text: &str) -> Result<Maze, LoadError> {
+    ron::de::from_str(text).map_err(LoadError::Ron)
+}
+
+/// Compact format for saving and sharing mazes.
+pub fn to_binary(maze: &Maze) -> Vec<u8> {
+    let mut result = MAGIC.to_vec();
+    result.push(BINARY_VERSION);
+    bincode::DefaultOptions::new().serialize_into(&mut result, maze)
+        .expect("Maze can't be serialized");
+    result
+}
+
+pub fn from_binary(bytes: &[u8]) -> Result<Maze, LoadError> {
+    if bytes.len() <= MAGIC.len() || !bytes.starts_with(MAGIC) {
+        return Err(LoadError::NotAMaze);
+    }
+    match bytes[MAGIC.len()] {
+        BINARY_VERSION => bincode::DefaultOptions::new()
+            .deserialize(&bytes[MAGIC.len() + 1..])
+            .map_err(LoadError::Binary),
+        version => Err(LoadError::UnsupportedVersion(version)),
+    }
+}
+
+#[test]
+fn test_round_trip() {
+    use rand::SeedableRng;
+    use rand::rngs::SmallRng;
+    use crate::build::MazeBuilder;
+    use crate::geometry::Dir;
+    use crate::geometry_sets::make_circle;
+    use crate::visible_area::DEFAULT_VISIBILITY_RADIUS;
+
+    let mut rng = SmallRng::seed_from_u64(1);
+    let mut builder = MazeBuilder::new(
+        make_circle(15).collect(), Default::default(), DEFAULT_VISIBILITY_RADIUS, &mut rng
+    );
+    let first = builder.generate_first_layer((0, 0));
+    let last = builder.add_layer_from_deepest_point(first).unwrap();
+    builder.set_finish_at_deepest_point(last);
+    let mut maze = builder.into_maze();
+    for &dir in &[Dir::UP, Dir::RIGHT, Dir::DOWN, Dir::LEFT] {
+        maze.try_move(dir);
+    }
+
+    let same = |loaded: &Maze| {
+        assert_eq!(loaded.position(), maze.position());
+        assert_eq!(loaded.current_layer_index, maze.current_layer_index);
+        assert_eq!(loaded.path_from_start, maze.path_from_start);
+        assert_eq!(loaded.finish, maze.finish);
+        assert_eq!(loaded.layers.len(), maze.layers.len());
+        for (loaded, original) in loaded.layers.iter().zip(&maze.layers) {
+            let cells: Vec<_> = original.layer.cells().collect();
+            assert_eq!(loaded.layer.cells().collect::<Vec<_>>(), cells);
+            for &cell in &cells {
+                for &dir in original.layer.directions(cell) {
+                    assert_eq!(loaded.layer.passable(cell, dir), original.layer.passable(cell, dir));
+                }
+                assert_eq!(loaded.layer.reachable(cells[0], cell), original.layer.reachable(cells[0], cell));
+            }
+        }
+        assert!(loaded.current_layer().cells().eq(maze.current_layer().cells()));
+    };
+    same(&from_ron(&to_ron(&maze).unwrap()).unwrap());
+    let binary = to_binary(&maze);
+    same(&from_binary(&binary).unwrap());
+
+    let mut newer = binary.clone();
+    newer[MAGIC.len()] = BINARY_VERSION + 1;
+    assert!(matches!(from_binary(&newer), Err(LoadError::UnsupportedVersion(_))));
+    assert!(matches!(from_binary(&binary[..binary.len() / 2]), Err(LoadError::Binary(_))));
+    assert!(matches!(from_binary(b"maze"), Err(LoadError::NotAMaze)));
+
+    let mut moved_away = maze.clone();
+    moved_away.position = (100, 100);
+    assert!(matches!(from_ron(&to_ron(&moved_away).unwrap()), Err(LoadError::Ron(_))));
+    let mut respawned = maze.clone();
+    respawned.spawn_point = (100, 100);
+    assert!(matches!(from_ron(&to_ron(&respawned).unwrap()), Err(LoadError::Ron(_))));
+    let mut miscounted = maze.clone();
+    miscounted.items_left += 1;
+    assert!(matches!(from_ron(&to_ron(&miscounted).unwrap()), Err(LoadError::Ron(_))));
+}
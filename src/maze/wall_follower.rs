@@ -0,0 +1,152 @@
+use std::collections::HashSet;
+
+use crate::geometry::Dir;
+use crate::maze::{Maze, MoveResult};
+
+
+/// Followers which haven't found the finish after this many moves
+/// are considered defeated. No player would walk that far.
+pub const MAX_MOVES: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hand {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The finish was reached after the given number of moves.
+    Finish(usize),
+    /// The follower came back to the same cell of the same layer going in
+    /// the same direction, so it is going to walk in circles forever.
+    Loop(usize),
+    /// Neither of the above happened in the allowed number of moves.
+    GaveUp,
+}
+
+impl Outcome {
+    /// Tells whether the maze can't be solved by sticking to the wall.
+    pub fn is_defeat(self) -> bool {
+        !matches!(self, Outcome::Finish(_))
+    }
+}
+
+/// Walks through the maze from the current position keeping `hand` on the
+/// wall, just as a player sticking to the wall would do. Moves are made
+/// with `Maze::try_move`, so transitions between layers are taken.
+pub fn follow_wall(maze: &mut Maze, hand: Hand, max_moves: usize) -> Outcome {
+    let topology = maze.current_layer().topology();
+    let rotate = |coord, dir| match hand {
+        Hand::Left => topology.rotate_clockwise(coord, dir),
+        Hand::Right => topology.rotate_counterclockwise(coord, dir),
+    };
+
+    // The direction to the cell the follower came from. There is no such
+    // cell at the beginning, so any direction will do.
+    let mut back = maze.current_layer().directions(maze.position())[0];
+    let mut visited: HashSet<((i32, i32), Dir, usize)> = HashSet::new();
+    for moves in 0..max_moves {
+        if !visited.insert((maze.position(), back, maze.current_layer_index)) {
+            return Outcome::Loop(moves);
+        }
+
+        // Turning towards the hand first and going back only if there is
+        // no other way.
+        let position = maze.position();
+        let mut dir = back;
+        let result = loop {
+            dir = rotate(position, dir);
+            match maze.try_move(dir) {
+                MoveResult::Obstacle if dir != back => continue,
+                result => break result,
+            }
+        };
+        match result {
+            MoveResult::Finish => return Outcome::Finish(moves + 1),
+            // The follower is walled in.
+            MoveResult::Obstacle => return Outcome::Loop(moves),
+            MoveResult::MovedToVisited | MoveResult::MovedToUntouched => back = dir.opposite(),
+            // Coming back to the same cell with fewer items left isn't a loop.
+            MoveResult::PickedUp => {
+                visited.clear();
+                back = dir.opposite();
+            },
+        }
+    }
+    Outcome::GaveUp
+}
+
+#[test]
+fn test_follow_wall() {
+    use crate::layer::Layer;
+
+    // A T-shaped layer with the finish at the end of the left arm.
+    let mut layer = Layer::from_shape(&[(0, 0), (1, 0), (2, 0), (1, 1)]);
+    layer.join((0, 0), Dir::RIGHT);
+    layer.join((1, 0), Dir::RIGHT);
+    layer.join((1, 0), Dir::DOWN);
+    for &(hand, moves) in &[(Hand::Left, 2), (Hand::Right, 4)] {
+        let mut maze = Maze::new(layer.clone(), (1, 1));
+        maze.set_finish((0, 0, 0));
+        assert_eq!(follow_wall(&mut maze, hand, 100), Outcome::Finish(moves));
+    }
+
+    let mut maze = Maze::new(layer, (1, 1));
+    maze.set_finish((0, 0, 0));
+    assert_eq!(follow_wall(&mut maze, Hand::Right, 3), Outcome::GaveUp);
+}
+
+#[test]
+fn test_tricky_square_wall_followers() {
+    use crate::levels::{LevelGenerator, TrickySquare};
+
+    // The level is meant to defeat players sticking to a wall, but on its
+    // first stages both hands reach the finish. Its stages have to stay the
+    // same, so mazes solved this way can't be rejected, and the test only
+    // records how the level behaves.
+    for stage in 0..TrickySquare().recommended_length() {
+        for &hand in &[Hand::Left, Hand::Right] {
+            let mut maze = TrickySquare().generate(stage, u64::from(stage)).unwrap();
+            let outcome = follow_wall(&mut maze, hand, MAX_MOVES);
+            assert!(
+                matches!(outcome, Outcome::Finish(_)),
+                "{:?} hand is defeated on stage {}: {:?}", hand, stage, outcome
+            );
+        }
+    }
+}
+
+#[test]
+fn test_loop_defeats_wall_followers() {
+    use crate::layer::Layer;
+    use itertools::Itertools;
+
+    // A ring of cells around the finish, which is entered from the bottom.
+    // Both hands keep to the outer wall and walk around the ring forever,
+    // which shows once they are back at the cell after the spawn point.
+    let shape: Vec<_> = (0..3).cartesian_product(0..3).collect();
+    let mut layer = Layer::from_shape(&shape);
+    for &(cell, dir) in &[
+        ((0, 0), Dir::RIGHT), ((1, 0), Dir::RIGHT), ((2, 0), Dir::DOWN), ((2, 1), Dir::DOWN),
+        ((0, 0), Dir::DOWN), ((0, 1), Dir::DOWN), ((0, 2), Dir::RIGHT), ((1, 2), Dir::RIGHT),
+        ((1, 1), Dir::DOWN),
+    ] {
+        layer.join(cell, dir);
+    }
+    for &hand in &[Hand::Left, Hand::Right] {
+        let mut maze = Maze::new(layer.clone(), (0, 0));
+        maze.set_finish((1, 1, 0));
+        assert_eq!(follow_wall(&mut maze, hand, 100), Outcome::Loop(9));
+    }
+}
+
+#[test]
+fn test_walled_in_follower() {
+    use crate::layer::Layer;
+
+    let layer = Layer::from_shape(&[(0, 0), (1, 0)]);
+    let mut maze = Maze::new(layer, (0, 0));
+    maze.set_finish((1, 0, 0));
+    assert_eq!(follow_wall(&mut maze, Hand::Left, 100), Outcome::Loop(0));
+}
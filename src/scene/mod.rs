@@ -1,5 +1,7 @@
 mod model;
 mod render;
+mod recording;
 
 pub use model::{Scene, Camera};
 pub use render::Renderer;
+pub use recording::{Action, RecordedAction, Recording, Playback, recording_requested};
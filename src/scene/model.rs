@@ -1,8 +1,9 @@
 use std::collections::{HashMap, VecDeque};
 use std::time::Duration;
 
-use crate::geometry::{Dir, DIRECTIONS};
+use crate::geometry::Dir;
 use crate::maze::{Maze, MoveResult};
+use crate::scene::Action;
 use crate::visible_area::visible_area;
 use crate::utils::tuple_arithmetic::{distance, linear_interpolation};
 
@@ -11,6 +12,8 @@ const MIN_BRIGHTNESS: u8 = 96;
 const HINT_USAGE_ALLOWED_INTERVAL: Duration = Duration::from_secs(60);
 
 
+/// Position of the center of the screen in the coordinates of
+/// `Topology::center`.
 pub type Camera = (f32, f32);
 
 #[derive(Clone, Copy)]
@@ -31,6 +34,8 @@ pub struct Scene {
     pub camera: Camera,
     pub level_id: &'static str,
     pub stage: u32,
+    /// Time spent playing the stage.
+    pub elapsed: Duration,
 
     pub visual_info: HashMap<(i32, i32), VisualInfo>,
     state: State,
@@ -69,11 +74,12 @@ impl Scene {
         level_id: &'static str,
         stage: u32
     ) -> Scene {
-        let player_pos = maze.position();
+        let camera = maze.current_layer().topology().center(maze.position());
         let mut result = Scene {
             maze,
-            camera: (player_pos.0 as f32, player_pos.1 as f32),
+            camera,
             level_id, stage,
+            elapsed: Duration::from_secs(0),
             visual_info: HashMap::new(),
             state: State::Idle,
             time_since_hint_usage: Duration::from_secs(0),
@@ -89,6 +95,7 @@ impl Scene {
             info.update(elapsed);
         }
         self.time_since_hint_usage += elapsed;
+        self.elapsed += elapsed;
     }
 
     fn update_scheduled_movement(&mut self, elapsed: Duration) {
@@ -99,7 +106,7 @@ impl Scene {
             while time_since_movement >= MOVEMENT_INTERVAL {
                 time_since_movement -= MOVEMENT_INTERVAL;
                 match self.maze.try_move_towards_finish() {
-                    MoveResult::MovedToUntouched | MoveResult::Finish => {
+                    MoveResult::MovedToUntouched | MoveResult::PickedUp | MoveResult::Finish => {
                         finish_movement = true;
                         break;
                     },
@@ -117,9 +124,9 @@ impl Scene {
 
     fn update_camera(&mut self, elapsed: Duration) {
         const ACCELERATION_PER_MS: f32 = 0.997;
-        let pos = self.maze.position();
+        let pos = self.maze.current_layer().topology().center(self.maze.position());
         if distance(pos, self.camera) < 0.05 {
-            self.camera = (pos.0 as f32, pos.1 as f32);
+            self.camera = pos;
         } else {
             let ratio = ACCELERATION_PER_MS.powf(elapsed.as_millis() as f32);
             self.camera = linear_interpolation(pos, self.camera, ratio);
@@ -128,9 +135,7 @@ impl Scene {
 
     pub fn try_move(&mut self, dir: Dir) -> MoveResult {
         let result = self.maze.try_move(dir);
-        if result == MoveResult::MovedToVisited
-            || result == MoveResult::MovedToUntouched
-        {
+        if result != MoveResult::Obstacle {
             self.on_position_updated();
         }
         result
@@ -142,8 +147,37 @@ impl Scene {
         result
     }
 
+    pub fn undo(&mut self) -> MoveResult {
+        self.state = State::Idle;
+        let result = self.maze.undo();
+        self.on_position_updated();
+        result
+    }
+
+    pub fn redo(&mut self) -> MoveResult {
+        self.state = State::Idle;
+        let result = self.maze.redo();
+        self.on_position_updated();
+        result
+    }
+
+    /// Returns `None` for actions which don't move the player right away.
+    pub fn apply(&mut self, action: Action) -> Option<MoveResult> {
+        match action {
+            Action::Move(dir) => Some(self.try_move(dir)),
+            Action::MoveBackwards => Some(self.try_move_towards_start()),
+            Action::Undo => Some(self.undo()),
+            Action::Redo => Some(self.redo()),
+            Action::UseHint => {
+                self.use_hint();
+                None
+            },
+        }
+    }
+
     pub fn use_hint(&mut self) {
         if self.time_since_hint_usage >= HINT_USAGE_ALLOWED_INTERVAL {
+            self.maze.update_path_from_finish();
             self.state = State::MovingToFinish(Duration::from_secs(0));
             self.time_since_hint_usage = Duration::from_secs(0);
         }
@@ -155,7 +189,10 @@ impl Scene {
 
     fn recalculate_visual_info(&mut self) {
         let player_pos = self.maze.position();
-        let visible_area = visible_area().shifted_by(player_pos);
+        let layer = self.maze.current_layer();
+        let visible_area = visible_area(
+            layer.topology(), self.maze.visibility_radius(), player_pos
+        );
 
         for &cell in visible_area.cells() {
             self.visual_info.entry(cell).or_insert(VisualInfo {
@@ -178,9 +215,9 @@ impl Scene {
         let mut queue = VecDeque::from(vec![player_pos]);
         while let Some(pos) = queue.pop_front() {
             let distance = self.visual_info[&pos].distance;
-            for &dir in &DIRECTIONS {
+            for &dir in layer.directions(pos) {
                 let to = pos + dir;
-                if self.maze.current_layer().passable(pos, dir)
+                if layer.passable(pos, dir)
                     && visible_area.cells().contains(&to)
                     && !self.visual_info[&to].directly_reachable
                 {
@@ -193,3 +230,22 @@ impl Scene {
         }
     }
 }
+
+#[test]
+fn test_pickup_updates_scene() {
+    use crate::layer::Layer;
+
+    let mut layer = Layer::from_shape(&[(0, 0), (1, 0), (2, 0)]);
+    layer.join((0, 0), Dir::RIGHT);
+    layer.join((1, 0), Dir::RIGHT);
+    let mut maze = Maze::new(layer, (0, 0));
+    maze.set_finish((2, 0, 0));
+    maze.add_item((1, 0, 0));
+    let mut scene = Scene::new(maze, "test", 0);
+
+    assert_eq!(scene.try_move(Dir::RIGHT), MoveResult::PickedUp);
+    // Distances are counted from the cell with the picked up item.
+    assert_eq!(scene.visual_info[&(1, 0)].distance, 0);
+    assert_eq!(scene.visual_info[&(0, 0)].distance, 1);
+    assert_eq!(scene.visual_info[&(2, 0)].distance, 1);
+}
@@ -0,0 +1,158 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::geometry::Dir;
+use crate::maze::{Maze, storage};
+use crate::scene::Scene;
+use crate::seed_code::SeedCode;
+
+
+/// Something the player does in a scene.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Action {
+    Move(Dir),
+    MoveBackwards,
+    Undo,
+    Redo,
+    UseHint,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RecordedAction {
+    /// Time since the start of the stage.
+    pub time: Duration,
+    pub action: Action,
+}
+
+/// Everything needed to play a session back: the actions are applied
+/// to the maze the session started with.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Recording {
+    pub code: SeedCode,
+    /// The maze in the binary format, so that playback doesn't depend
+    /// on the generator staying the same.
+    pub initial_maze: Vec<u8>,
+    pub actions: Vec<RecordedAction>,
+}
+
+impl Recording {
+    /// Starts recording the session played in `maze`.
+    pub fn new(code: SeedCode, maze: &Maze) -> Self {
+        Self { code, initial_maze: storage::to_binary(maze), actions: Vec::new() }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let f = std::fs::File::open(path)?;
+        Ok(ron::de::from_reader(f)?)
+    }
+
+    /// Saves the recording into the data directory
+    /// and returns the path to it.
+    pub fn save(&self) -> std::io::Result<PathBuf> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let mut path = dirs::data_dir().unwrap();
+        path.push(format!("amazeing/recordings/{}-{}.ron", self.code, timestamp));
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .expect("Recording can't be serialized");
+        std::fs::write(&path, text)?;
+        Ok(path)
+    }
+}
+
+/// Recordings are only saved when the game is started with `--record`.
+pub fn recording_requested() -> bool {
+    std::env::args().any(|arg| arg == "--record")
+}
+
+/// Applies recorded actions to a scene at the time they were made.
+pub struct Playback {
+    pub scene: Scene,
+    initial_maze: Maze,
+    recording: Recording,
+    /// Index of the first action which isn't applied yet.
+    next_action: usize,
+}
+
+impl Playback {
+    pub fn new(recording: Recording) -> Result<Self, storage::LoadError> {
+        let maze = storage::from_binary(&recording.initial_maze)?;
+        Ok(Self {
+            scene: Self::create_scene(maze.clone(), recording.code),
+            initial_maze: maze,
+            recording,
+            next_action: 0,
+        })
+    }
+
+    fn create_scene(maze: Maze, code: SeedCode) -> Scene {
+        Scene::new(maze, code.generator().id(), code.stage)
+    }
+
+    pub fn time(&self) -> Duration {
+        self.scene.elapsed
+    }
+
+    /// The time of the last action.
+    pub fn duration(&self) -> Duration {
+        self.recording.actions.last().map_or(Duration::from_secs(0), |action| action.time)
+    }
+
+    /// Moves the time forward applying the actions made in between.
+    pub fn advance(&mut self, elapsed: Duration) {
+        let target = self.scene.elapsed + elapsed;
+        while let Some(recorded) = self.recording.actions.get(self.next_action) {
+            if recorded.time > target {
+                break;
+            }
+            // The scene is updated in the same steps as when it was
+            // recorded, so scheduled moves end up at the same cells.
+            self.scene.update(recorded.time.saturating_sub(self.scene.elapsed));
+            self.scene.apply(recorded.action);
+            self.next_action += 1;
+        }
+        self.scene.update(target.saturating_sub(self.scene.elapsed));
+    }
+
+    /// Jumps to the given time. Going back plays everything from the start.
+    pub fn seek(&mut self, time: Duration) {
+        if time < self.scene.elapsed {
+            self.scene = Self::create_scene(self.initial_maze.clone(), self.recording.code);
+            self.next_action = 0;
+        }
+        self.advance(time - self.scene.elapsed);
+    }
+}
+
+#[test]
+fn test_playback() {
+    let code = SeedCode::default_for(0, 0);
+    let maze = code.generator().generate(code.stage, code.seed).unwrap();
+
+    // Wandering around, bumping into walls most of the time.
+    let mut scene = Scene::new(maze.clone(), code.generator().id(), code.stage);
+    let mut recording = Recording::new(code, &maze);
+    let dirs = [Dir::UP, Dir::RIGHT, Dir::DOWN, Dir::LEFT, Dir::UP_RIGHT, Dir::DOWN_LEFT];
+    for i in 0..200 {
+        scene.update(Duration::from_millis(100));
+        let action = match i % 7 {
+            5 => Action::Undo,
+            6 => Action::MoveBackwards,
+            _ => Action::Move(dirs[i * 5 % dirs.len()]),
+        };
+        recording.actions.push(RecordedAction { time: scene.elapsed, action });
+        scene.apply(action);
+    }
+    let text = ron::ser::to_string(&recording).unwrap();
+    let recording: Recording = ron::de::from_str(&text).unwrap();
+
+    let mut playback = Playback::new(recording).unwrap();
+    playback.advance(Duration::from_secs(10));
+    let halfway = playback.scene.maze.position();
+    playback.advance(Duration::from_secs(20));
+    assert_eq!(playback.scene.maze.position(), scene.maze.position());
+    assert_eq!(playback.scene.maze.history(), scene.maze.history());
+
+    playback.seek(Duration::from_secs(10));
+    assert_eq!(playback.scene.maze.position(), halfway);
+}
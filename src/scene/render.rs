@@ -8,12 +8,18 @@ use sdl2::rect::Rect;
 use sdl2::render::Texture;
 use sdl2::render::WindowCanvas as Canvas;
 
-use crate::geometry::Dir;
-use crate::visible_area::{visibility_radius, visible_area};
+use crate::geometry::{Dir, Topology};
+use crate::layer::Layer;
+use crate::visible_area::visible_area;
 use crate::scene::{Scene, Camera};
 use crate::maze::CellInfo;
+use crate::utils::tuple_arithmetic::distance;
 
+/// Distance between centers of neighbouring cells in pixels.
 const CELL_SIZE: u32 = 17;
+/// Cells of non-square topologies are shrunk by this ratio to leave
+/// space for walls.
+const CELL_SHRINK: f32 = 1.0 - 1.5 / CELL_SIZE as f32;
 const DEBUG: bool = false;
 const INVISIBLE_CELLS_BRIGHTNESS: u8 = 96;
 
@@ -30,8 +36,8 @@ impl Renderer {
         }
     }
 
-    pub fn initialize(&mut self, canvas: &mut Canvas) {
-        let light_surface = create_light_surface();
+    pub fn initialize(&mut self, canvas: &mut Canvas, visibility_radius: i32) {
+        let light_surface = create_light_surface(visibility_radius);
         self.light_texture = Some(
             canvas.texture_creator().create_texture_from_surface(light_surface).unwrap()
         );
@@ -43,11 +49,15 @@ impl Renderer {
 
         self.window_size.set(canvas.output_size().unwrap());
 
-        self.render_current_layer(canvas, &scene);
+        self.render_current_layer(canvas, scene);
 
         if DEBUG {
-            for &cell in visible_area().shifted_by(scene.maze.position()).boundary() {
-                if scene.maze.current_layer().has(cell) {
+            let layer = scene.maze.current_layer();
+            let area = visible_area(
+                layer.topology(), scene.maze.visibility_radius(), scene.maze.position()
+            );
+            for &cell in area.boundary() {
+                if layer.has(cell) {
                     self.render_square(
                         canvas,
                         cell, Color::RGB(240, 240, 240), scene
@@ -81,9 +91,11 @@ impl Renderer {
         );
 
         if !DEBUG {
-            let mut light_center = self.to_view(scene.maze.position(), scene.camera);
-            light_center.0 += CELL_SIZE as i32 / 2;
-            light_center.1 += CELL_SIZE as i32 / 2;
+            let topology = scene.maze.current_layer().topology();
+            let light_center = self.to_view_point(
+                topology.center(scene.maze.position()), scene.camera
+            );
+            let light_center = (light_center.0 as i32, light_center.1 as i32);
 
             let light_texture = self.light_texture.as_ref().unwrap();
             let query = light_texture.query();
@@ -100,13 +112,15 @@ impl Renderer {
 
     fn render_current_layer(&self, canvas: &mut Canvas, scene: &Scene) {
         let layer = scene.maze.current_layer();
+        let topology = layer.topology();
 
         let cells_iter: Vec<_> = if DEBUG {
             const RENDER_SIZE: i32 = 20;
             let range = -RENDER_SIZE..=RENDER_SIZE;
             range.clone().cartesian_product(range).collect()
         } else {
-            visible_area().shifted_by(scene.maze.position()).cells().iter().cloned().collect()
+            visible_area(topology, scene.maze.visibility_radius(), scene.maze.position())
+                .cells().iter().cloned().collect()
         };
 
         for cell in cells_iter {
@@ -121,11 +135,17 @@ impl Renderer {
                         CellInfo::Untouched => Color::RGB(br, br, br),
                         CellInfo::Visited => Color::RGB(cmp::min(208, br), cmp::min(208, br), br),
                         CellInfo::Finish => Color::RGB(0, br / 4 * 3, 0),
+                        CellInfo::Item => Color::RGB(br, br / 4 * 3, br / 4),
                     });
                 } else {
                     canvas.set_draw_color(Color::RGB(br, br, br));
                 }
 
+                if topology != Topology::Square {
+                    self.render_polygonal_cell(canvas, layer, cell, scene.camera);
+                    continue;
+                }
+
                 let view_coord = self.to_view(cell, scene.camera);
 
                 self.fill_rect(canvas, view_coord.0, view_coord.1, CELL_SIZE - 1, CELL_SIZE - 1);
@@ -152,10 +172,94 @@ impl Renderer {
             255
         );
         canvas.set_draw_color(color);
+        let topology = scene.maze.current_layer().topology();
+        if topology != Topology::Square {
+            self.fill_polygon(canvas, &self.cell_polygon(topology, coord, camera));
+            return;
+        }
         let view_coord = self.to_view(coord, camera);
         self.fill_rect(canvas, view_coord.0, view_coord.1, CELL_SIZE - 1, CELL_SIZE - 1);
     }
 
+    /// Draws a cell along with passages to its neighbours
+    /// using the current draw color.
+    fn render_polygonal_cell(
+        &self,
+        canvas: &mut Canvas,
+        layer: &Layer<CellInfo>,
+        cell: (i32, i32),
+        camera: Camera
+    ) {
+        let topology = layer.topology();
+        let polygon = self.cell_polygon(topology, cell, camera);
+        self.fill_polygon(canvas, &polygon);
+
+        let closest_to = |polygon: &[(f32, f32)], point: (f32, f32)| {
+            let mut sorted = polygon.to_vec();
+            sorted.sort_by(|&a, &b| distance(a, point).partial_cmp(&distance(b, point)).unwrap());
+            sorted
+        };
+        for dir in topology.forward_directions(cell) {
+            if !layer.passable(cell, dir) {
+                continue;
+            }
+            // Passage fills the gap between the sides of the two cells
+            // facing each other.
+            let neighbour = cell + dir;
+            let neighbour_polygon = self.cell_polygon(topology, neighbour, camera);
+            let from = closest_to(&polygon, self.to_view_point(topology.center(neighbour), camera));
+            let to = closest_to(&neighbour_polygon, self.to_view_point(topology.center(cell), camera));
+            let (a, b) = (from[0], from[1]);
+            self.fill_polygon(canvas, &[a, b, closest_to(&to[..2], b)[0], closest_to(&to[..2], a)[0]]);
+        }
+    }
+
+    /// Vertices of a cell on the screen.
+    fn cell_polygon(&self, topology: Topology, cell: (i32, i32), camera: Camera) -> Vec<(f32, f32)> {
+        let center = topology.center(cell);
+        topology.vertices(cell).into_iter().map(|(x, y)| self.to_view_point(
+            (center.0 + x * CELL_SHRINK, center.1 + y * CELL_SHRINK),
+            camera
+        )).collect()
+    }
+
+    /// Transforms a point given in coordinates of `Topology::center` to
+    /// the screen coordinates. Centers of square cells stay in the same
+    /// places as with `to_view`.
+    fn to_view_point(&self, point: (f32, f32), scene_camera: Camera) -> (f32, f32) {
+        let window_size = self.window_size.get();
+        let half_cell = CELL_SIZE as f32 / 2.0;
+        (
+            (point.0 - scene_camera.0) * CELL_SIZE as f32 + window_size.0 as f32 / 2.0 + half_cell,
+            (point.1 - scene_camera.1) * CELL_SIZE as f32 + window_size.1 as f32 / 2.0 + half_cell,
+        )
+    }
+
+    /// Fills a convex polygon line by line.
+    fn fill_polygon(&self, canvas: &mut Canvas, points: &[(f32, f32)]) {
+        let min_y = points.iter().map(|p| p.1).fold(f32::INFINITY, f32::min).round() as i32;
+        let max_y = points.iter().map(|p| p.1).fold(f32::NEG_INFINITY, f32::max).round() as i32;
+
+        let mut lines = Vec::new();
+        for y in min_y..max_y {
+            let scan_y = y as f32 + 0.5;
+            let (mut min_x, mut max_x) = (f32::INFINITY, f32::NEG_INFINITY);
+            for (i, &a) in points.iter().enumerate() {
+                let b = points[(i + 1) % points.len()];
+                if (a.1 <= scan_y) != (b.1 <= scan_y) {
+                    let x = a.0 + (scan_y - a.1) / (b.1 - a.1) * (b.0 - a.0);
+                    min_x = min_x.min(x);
+                    max_x = max_x.max(x);
+                }
+            }
+            let (from, to) = (min_x.round() as i32, max_x.round() as i32);
+            if from < to {
+                lines.push(Rect::new(from, y, (to - from) as u32, 1));
+            }
+        }
+        canvas.fill_rects(&lines).unwrap();
+    }
+
     fn to_view(&self, scene_coord: (i32, i32), scene_camera: Camera) -> (i32, i32) {
         let window_size = self.window_size.get();
         let view_camera = (window_size.0 as f32 / 2.0, window_size.1 as f32 / 2.0);
@@ -179,9 +283,9 @@ impl Renderer {
     }
 }
 
-fn create_light_surface() -> Surface<'static> {
-    let radius = ((visibility_radius() as f32 - 1. / 2_f32.sqrt()) * CELL_SIZE as f32) as u32;
-    let size = visibility_radius() as u32 * 2 * CELL_SIZE;
+fn create_light_surface(visibility_radius: i32) -> Surface<'static> {
+    let radius = ((visibility_radius as f32 - 1. / 2_f32.sqrt()) * CELL_SIZE as f32) as u32;
+    let size = visibility_radius as u32 * 2 * CELL_SIZE;
     let center = size / 2;
 
     let surface = Surface::new(size, size, sdl2::pixels::PixelFormatEnum::RGBA32).unwrap();
@@ -110,4 +110,11 @@ impl<S: Screen> Screen for FadingScreen<S> {
         canvas.set_blend_mode(sdl2::render::BlendMode::Blend);
         canvas.fill_rect(canvas.viewport()).unwrap();
     }
+
+    fn quit(&mut self) {
+        // A screen which is fading out has already been left.
+        if let State::FadingIn(_) | State::Active = self.state {
+            self.screen.quit();
+        }
+    }
 }
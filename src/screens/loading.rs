@@ -8,68 +8,113 @@ use crate::screens::{
 };
 use crate::ui::text_view::TextView;
 use crate::maze::Maze;
-use crate::levels::LevelGenerator;
+use crate::build::GenerationError;
+use crate::levels::RetryPolicy;
+use crate::seed_code::SeedCode;
 
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
 
 
+/// Generation fails once in a while and is retried. Failing this many
+/// times means that something is wrong, so the player is told about it.
+const FAILURES_TO_REPORT: u32 = 10;
+
 enum State {
     Waiting,
     WaitingForGeneration,
     WaitingForKeyPress(Box<dyn Screen>),
+    /// The generator gave up, any key leads back to the menu.
+    Failed,
+}
+
+/// Sent by the generating thread.
+enum Message {
+    Failure(GenerationError),
+    Done(Result<Box<Maze>, GenerationError>),
 }
 
 pub struct LoadingScreen {
     state: State,
-    receiver: Receiver<Maze>,
-    level_id: &'static str,
-    stage: u32,
+    receiver: Receiver<Message>,
+    code: SeedCode,
     autocontinue: bool,
+    failures: u32,
     main_text: String,
+    details: Option<String>,
 
     main_text_view: Option<TextView>,
     press_any_key_text: Option<TextView>,
+    details_view: Option<TextView>,
 }
 
 impl LoadingScreen {
     pub fn new(
-        generator: &'static dyn LevelGenerator,
-        stage: u32,
+        code: SeedCode,
         autocontinue: bool,
     ) -> FadingScreen<Self> {
+        let generator = code.generator();
         let (sender, receiver) = channel();
         std::thread::spawn(move|| {
-            if sender.send(generator.generate(stage)).is_err() {
+            // No fallback stage, as the maze is shown and saved as the one
+            // of the requested stage.
+            let result = generator.generate_with(
+                code.stage, code.seed, &RetryPolicy::default(),
+                &mut |error| { let _ = sender.send(Message::Failure(error.clone())); }
+            );
+            if sender.send(Message::Done(result.map(Box::new))).is_err() {
                 /* The receiving end is disconnected. Drop the result. */
             };
         });
 
         let state;
         let text;
-        if stage == 0 && autocontinue {
+        if code.stage == 0 && autocontinue {
             state = State::Waiting;
             text = generator.intro_text();
         } else {
             state = State::WaitingForGeneration;
             text = "Generating...";
         };
-        let level_id = generator.id();
         FadingScreen::new(
             Self {
                 state,
                 receiver,
-                level_id,
-                stage,
+                code,
                 autocontinue,
+                failures: 0,
                 main_text: text.to_string(),
+                details: None,
                 main_text_view: None,
                 press_any_key_text: None,
+                details_view: None,
             },
             Duration::from_millis(400),
             Duration::from_millis(400),
         )
     }
+
+    /// Replaces the screen with the same one in `state` telling what went
+    /// wrong. Texts can only be rendered in `initialize`.
+    fn show_error(&mut self, state: State, text: &str, error: &GenerationError) -> Transition {
+        let (_, disconnected) = channel();
+        Transition::GotoNow(Box::new(Self {
+            state,
+            receiver: std::mem::replace(&mut self.receiver, disconnected),
+            code: self.code,
+            autocontinue: self.autocontinue,
+            failures: self.failures,
+            main_text: text.to_string(),
+            details: Some(error.to_string()),
+            main_text_view: None,
+            press_any_key_text: None,
+            details_view: None,
+        }))
+    }
+
+    fn next_screen(&self, maze: Maze) -> Box<dyn Screen> {
+        Box::new(SceneScreen::from_maze(maze, self.code, self.autocontinue))
+    }
 }
 
 impl Screen for LoadingScreen {
@@ -77,26 +122,31 @@ impl Screen for LoadingScreen {
         self.main_text_view.as_mut().unwrap().update(elapsed);
         self.press_any_key_text.as_mut().unwrap().update(elapsed);
 
-        if let State::WaitingForKeyPress(_) = self.state {
+        if let State::WaitingForKeyPress(_) | State::Failed = self.state {
             return Transition::Stay;
         }
         match self.receiver.try_recv() {
-            Ok(maze) => {
+            Ok(Message::Failure(error)) => {
+                self.failures += 1;
+                if self.failures == FAILURES_TO_REPORT {
+                    let state = std::mem::replace(&mut self.state, State::WaitingForGeneration);
+                    self.show_error(state, "Generation keeps failing...", &error)
+                } else {
+                    Transition::Stay
+                }
+            },
+            Ok(Message::Done(Err(error))) =>
+                self.show_error(State::Failed, "Could not generate the maze", &error),
+            Ok(Message::Done(Ok(maze))) => {
                 if let State::Waiting = self.state {
-                    self.state = State::WaitingForKeyPress(
-                        Box::new(SceneScreen::from_maze(
-                            maze, self.level_id, self.stage, self.autocontinue
-                        ))
-                    );
+                    self.state = State::WaitingForKeyPress(self.next_screen(*maze));
                     self.press_any_key_text.as_mut().unwrap().show_pulsating(
                         Duration::from_millis(800),
                         128, 255
                     );
                     Transition::Stay
                 } else {
-                    Transition::Goto(Box::new(SceneScreen::from_maze(
-                        maze, self.level_id, self.stage, self.autocontinue
-                    )))
+                    Transition::Goto(self.next_screen(*maze))
                 }
             },
             Err(TryRecvError::Empty) => Transition::Stay,
@@ -119,6 +169,7 @@ impl Screen for LoadingScreen {
                 Transition::Stay
             },
             State::WaitingForGeneration => Transition::Stay,
+            State::Failed => Transition::GotoNow(MenuScreen::create()),
             State::WaitingForKeyPress(_) => {
                 if let State::WaitingForKeyPress(new_screen)
                     = std::mem::replace(&mut self.state, State::Waiting)
@@ -150,9 +201,13 @@ impl Screen for LoadingScreen {
         self.main_text_view = Some(main_text_view);
 
 
+        let press_any_key = match self.state {
+            State::Failed => "Press any key to return to the menu",
+            _ => "Press any key to continue",
+        };
         let mut press_any_key_text = TextView::new(
             canvas,
-            "Press any key to continue",
+            press_any_key,
             &fonts.small,
             Color::RGB(128, 128, 128),
             700
@@ -164,8 +219,30 @@ impl Screen for LoadingScreen {
         );
         press_any_key_text_rect.set_y(main_text_rect.bottom() + 18);
         press_any_key_text.set_dst_rect(press_any_key_text_rect);
+        if let State::Failed = self.state {
+            press_any_key_text.show_pulsating(Duration::from_millis(800), 128, 255);
+        }
 
         self.press_any_key_text = Some(press_any_key_text);
+
+        if let Some(details) = &self.details {
+            let mut details_view = TextView::new(
+                canvas,
+                details,
+                &fonts.small,
+                Color::RGB(192, 64, 64),
+                975
+            );
+            let mut details_rect = Rect::from_center(
+                center,
+                details_view.width(),
+                details_view.height()
+            );
+            details_rect.set_y(press_any_key_text_rect.bottom() + 18);
+            details_view.set_dst_rect(details_rect);
+            details_view.show();
+            self.details_view = Some(details_view);
+        }
     }
 
     fn render(&self, canvas: &mut Canvas) {
@@ -177,5 +254,9 @@ impl Screen for LoadingScreen {
 
         let press_any_key_text = self.press_any_key_text.as_ref().unwrap();
         press_any_key_text.render(canvas);
+
+        if let Some(details_view) = &self.details_view {
+            details_view.render(canvas);
+        }
     }
 }
@@ -38,6 +38,7 @@ impl ScreenManager {
 
     pub fn handle_event(&mut self, event: &Event) -> Transition {
         if let Event::Quit {..} = event {
+            self.current_screen.quit();
             self.is_running = false;
             Transition::Exit
         } else {
@@ -4,26 +4,37 @@ use sdl2::pixels::Color;
 
 use crate::screens::*;
 use crate::screens::loading::LoadingScreen;
+use crate::screens::scene::SceneScreen;
+use crate::screens::seed_entry::SeedEntryScreen;
 use crate::screens::fading::FadingScreen;
 use crate::levels::*;
 use crate::levels;
 use crate::geometry::Dir;
 use crate::utils::persistent_state::get_persistent_state;
+use crate::seed_code::SeedCode;
+use crate::ui::text_view::TextView;
 
 pub struct MenuScreen {
     levels: Vec<(&'static dyn LevelGenerator, u32)>,
     cursor: (u32, u32),
     recommended_level: (u32, u32),
+    has_unfinished_stage: bool,
+
+    controls_text: Option<TextView>,
 }
 
 impl MenuScreen {
     fn new() -> Self {
         let levels = Self::get_unlocked_levels();
         let recommended_level = Self::find_recommended_level(&levels);
+        let has_unfinished_stage = get_persistent_state().lock().unwrap()
+            .unfinished_stage.is_some();
         Self {
             levels,
             cursor: recommended_level,
-            recommended_level
+            recommended_level,
+            has_unfinished_stage,
+            controls_text: None,
         }
     }
 
@@ -38,7 +49,7 @@ impl MenuScreen {
 
     pub fn create_initial() -> Box<dyn Screen> {
         let menu_screen = Self::new();
-        if menu_screen.recommended_level == (0, 0) {
+        if menu_screen.recommended_level == (0, 0) && !menu_screen.has_unfinished_stage {
             menu_screen.start_level(menu_screen.recommended_level)
         } else {
             menu_screen.with_effects()
@@ -86,17 +97,42 @@ impl MenuScreen {
     }
 
     fn start_level(&self, level: (u32, u32)) -> Box<dyn Screen> {
-        let (generator, completed_stages) = self.levels[level.0 as usize];
+        let (_generator, completed_stages) = self.levels[level.0 as usize];
         let stage = level.1;
         assert!(stage <= completed_stages);
         let autocontinue = self.recommended_level <= level;
-        Box::new(LoadingScreen::new(generator, stage, autocontinue))
+        let code = SeedCode::default_for(level.0 as usize, stage);
+        Box::new(LoadingScreen::new(code, autocontinue))
+    }
+
+    fn start_level_with_random_seed(&self, level: (u32, u32)) -> Box<dyn Screen> {
+        let (_generator, completed_stages) = self.levels[level.0 as usize];
+        assert!(level.1 <= completed_stages);
+        let code = SeedCode::random(level.0 as usize, level.1);
+        Box::new(LoadingScreen::new(code, false))
+    }
+
+    /// Stages which can't be loaded are forgotten, and the menu is shown
+    /// again without them.
+    fn continue_unfinished_stage(&self) -> Box<dyn Screen> {
+        let mut persistent_state = get_persistent_state().lock().unwrap();
+        match persistent_state.load_unfinished_stage() {
+            Some((stage, maze)) => Box::new(SceneScreen::resume(maze, stage)),
+            None => {
+                persistent_state.forget_unfinished_stage();
+                drop(persistent_state);
+                Self::create()
+            },
+        }
     }
 }
 
 enum Action {
     Exit,
+    Continue,
     StartLevel,
+    StartLevelWithRandomSeed,
+    EnterSeedCode,
     MoveCursor(Dir),
     Nothing,
 }
@@ -120,13 +156,29 @@ impl Screen for MenuScreen {
             Event::KeyDown { keycode: Some(Keycode::Return), .. } => {
                 Action::StartLevel
             },
+            Event::KeyDown { keycode: Some(Keycode::C), .. } if self.has_unfinished_stage => {
+                Action::Continue
+            },
+            Event::KeyDown { keycode: Some(Keycode::R), .. } => {
+                Action::StartLevelWithRandomSeed
+            },
+            Event::KeyDown { keycode: Some(Keycode::S), .. } => {
+                Action::EnterSeedCode
+            },
             _ => Action::Nothing
         };
 
         match action {
             Action::Exit => Transition::Exit,
             Action::Nothing => Transition::Stay,
+            Action::Continue => Transition::Goto(self.continue_unfinished_stage()),
             Action::StartLevel => Transition::Goto(self.start_level(self.cursor)),
+            Action::StartLevelWithRandomSeed => {
+                Transition::Goto(self.start_level_with_random_seed(self.cursor))
+            },
+            Action::EnterSeedCode => Transition::Goto(SeedEntryScreen::create(
+                self.levels.iter().map(|&(_, completed)| completed).collect()
+            )),
             Action::MoveCursor(dir) => {
                 match dir {
                     Dir::UP => {
@@ -154,15 +206,41 @@ impl Screen for MenuScreen {
                             self.cursor.1 += 1;
                         }
                     },
+                    Dir::UP_RIGHT | Dir::DOWN_LEFT => {},
                 }
                 Transition::Stay
             },
         }
     }
 
+    fn initialize(&mut self, canvas: &mut Canvas, fonts: &Fonts) {
+        const MARGIN: i32 = 52;
+        let mut controls = String::new();
+        if self.has_unfinished_stage {
+            controls.push_str("C: continue    ");
+        }
+        controls.push_str("Enter: play    R: play with a random seed    S: enter a seed code");
+        let mut controls_text = TextView::new(
+            canvas,
+            &controls,
+            &fonts.small,
+            Color::RGB(96, 96, 96),
+            1300
+        );
+        controls_text.set_dst_rect(Rect::new(
+            MARGIN,
+            canvas.viewport().bottom() - controls_text.height() as i32 - MARGIN,
+            controls_text.width(),
+            controls_text.height()
+        ));
+        controls_text.show();
+        self.controls_text = Some(controls_text);
+    }
+
     fn render(&self, canvas: &mut Canvas) {
         canvas.set_draw_color(Color::RGB(32, 32, 32));
         canvas.clear();
+        self.controls_text.as_ref().unwrap().render(canvas);
 
         render_button_in_grid(canvas, self.cursor.0, self.cursor.1, Color::RGB(192, 192, 192), true);
         for (i, &(_, completed)) in self.levels.iter().enumerate() {
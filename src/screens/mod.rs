@@ -1,6 +1,8 @@
 pub mod scene;
 pub mod menu;
 pub mod loading;
+pub mod seed_entry;
+pub mod replay;
 mod fading;
 mod manager;
 
@@ -33,4 +35,6 @@ pub trait Screen {
         Transition::Stay
     }
     fn render(&self, _canvas: &mut Canvas) {}
+    /// Called when the window is closed, the screen isn't used after that.
+    fn quit(&mut self) {}
 }
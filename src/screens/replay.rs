@@ -0,0 +1,103 @@
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+
+use crate::scene::{self, Playback};
+use crate::screens::{
+    *,
+    menu::MenuScreen,
+    fading::FadingScreen,
+};
+use crate::ui::text_view::TextView;
+
+const SPEEDS: [u32; 3] = [1, 4, 16];
+/// How far the left and right arrows move through the recording.
+const SCRUB_STEP: Duration = Duration::from_secs(5);
+
+/// Plays a recorded session back, letting the viewer pause it,
+/// speed it up and jump back and forth.
+pub struct ReplayScreen {
+    playback: Playback,
+    renderer: scene::Renderer,
+    speed: u32,
+    paused: bool,
+
+    controls_text: Option<TextView>,
+}
+
+impl ReplayScreen {
+    pub fn new(playback: Playback) -> FadingScreen<Self> {
+        FadingScreen::new(
+            Self {
+                playback,
+                renderer: scene::Renderer::new(),
+                speed: SPEEDS[0],
+                paused: false,
+                controls_text: None,
+            },
+            Duration::from_millis(0), // Maze is initially shadowed anyways
+            Duration::from_millis(700),
+        )
+    }
+}
+
+impl Screen for ReplayScreen {
+    fn handle_event(&mut self, event: &Event) -> Transition {
+        let time = self.playback.time();
+        match event {
+            Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                return Transition::GotoNow(MenuScreen::create());
+            },
+            Event::KeyDown { keycode: Some(Keycode::Space), .. } => {
+                self.paused = !self.paused;
+            },
+            Event::KeyDown { keycode: Some(Keycode::Num1), .. } => self.speed = SPEEDS[0],
+            Event::KeyDown { keycode: Some(Keycode::Num2), .. } => self.speed = SPEEDS[1],
+            Event::KeyDown { keycode: Some(Keycode::Num3), .. } => self.speed = SPEEDS[2],
+            Event::KeyDown { keycode: Some(Keycode::Left), .. } => {
+                self.playback.seek(time.saturating_sub(SCRUB_STEP));
+            },
+            Event::KeyDown { keycode: Some(Keycode::Right), .. } => {
+                self.playback.seek((time + SCRUB_STEP).min(self.playback.duration()).max(time));
+            },
+            _ => {},
+        }
+        Transition::Stay
+    }
+
+    fn update(&mut self, elapsed: Duration) -> Transition {
+        if !self.paused {
+            self.playback.advance(elapsed * self.speed);
+        }
+        Transition::Stay
+    }
+
+    fn initialize(&mut self, canvas: &mut Canvas, fonts: &Fonts) {
+        self.renderer.initialize(canvas, self.playback.scene.maze.visibility_radius());
+
+        const MARGIN: i32 = 12;
+        let mut controls_text = TextView::new(
+            canvas,
+            &format!(
+                "Space: pause    1, 2, 3: speed x{}, x{}, x{}    Left, Right: scrub",
+                SPEEDS[0], SPEEDS[1], SPEEDS[2]
+            ),
+            &fonts.small,
+            Color::RGB(96, 96, 96),
+            700
+        );
+        let viewport = canvas.viewport();
+        controls_text.set_dst_rect(Rect::new(
+            viewport.right() - controls_text.width() as i32 - MARGIN,
+            viewport.bottom() - controls_text.height() as i32 - MARGIN,
+            controls_text.width(),
+            controls_text.height()
+        ));
+        controls_text.show();
+        self.controls_text = Some(controls_text);
+    }
+
+    fn render(&self, canvas: &mut Canvas) {
+        self.renderer.render(&self.playback.scene, canvas);
+        self.controls_text.as_ref().unwrap().render(canvas);
+    }
+}
@@ -1,37 +1,123 @@
 use crate::geometry::Dir;
 use crate::maze::{Maze, MoveResult};
-use crate::scene;
+use crate::scene::{self, Action, RecordedAction, Recording, recording_requested};
 use crate::screens::{
     *,
     menu::MenuScreen,
     fading::FadingScreen,
 };
 use crate::observers::{level_completion_observer, LevelCompleted};
+use crate::seed_code::SeedCode;
+use crate::ui::text_view::TextView;
+use crate::utils::persistent_state::{get_persistent_state, UnfinishedStage};
+
+use sdl2::keyboard::Mod;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+
+
+/// How often the stage is saved, so that it can be continued
+/// even if the game crashes.
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(10);
 
 pub struct SceneScreen {
     scene: scene::Scene,
     renderer: scene::Renderer,
     autocontinue: bool,
+    code: SeedCode,
+    time_since_save: Duration,
+    /// The number of actions made when the stage was saved the last time,
+    /// `None` if it hasn't been saved yet.
+    saved_actions: Option<usize>,
+    recording: Recording,
+
+    seed_code_text: Option<TextView>,
 }
 
 impl SceneScreen {
     pub fn from_maze(
         maze: Maze,
-        level_id: &'static str,
-        stage: u32,
+        code: SeedCode,
+        autocontinue: bool,
+    ) -> FadingScreen<Self> {
+        let recording = Recording::new(code, &maze);
+        Self::new(maze, code, autocontinue, Duration::from_secs(0), recording, None)
+    }
+
+    /// Restores the scene the player has left.
+    pub fn resume(maze: Maze, stage: UnfinishedStage) -> FadingScreen<Self> {
+        // Stages saved before their initial mazes were kept are recorded
+        // from where the player left them.
+        let recording = if stage.initial_maze.is_empty() {
+            Recording::new(stage.code, &maze)
+        } else {
+            Recording {
+                code: stage.code,
+                initial_maze: stage.initial_maze,
+                actions: stage.actions,
+            }
+        };
+        let saved_actions = Some(recording.actions.len());
+        Self::new(maze, stage.code, stage.autocontinue, stage.elapsed, recording, saved_actions)
+    }
+
+    fn new(
+        maze: Maze,
+        code: SeedCode,
         autocontinue: bool,
+        elapsed: Duration,
+        recording: Recording,
+        saved_actions: Option<usize>,
     ) -> FadingScreen<Self> {
+        let mut scene = scene::Scene::new(maze, code.generator().id(), code.stage);
+        scene.elapsed = elapsed;
         FadingScreen::new(
             Self {
-                scene: scene::Scene::new(maze, level_id, stage),
+                scene,
                 renderer: scene::Renderer::new(),
                 autocontinue,
+                code,
+                time_since_save: Duration::from_secs(0),
+                saved_actions,
+                recording,
+                seed_code_text: None,
             },
             Duration::from_millis(0), // Maze is initially shadowed anyways
             Duration::from_millis(700),
         )
     }
 
+    fn save(&mut self) {
+        get_persistent_state().lock().unwrap().save_unfinished_stage(
+            UnfinishedStage {
+                code: self.code,
+                autocontinue: self.autocontinue,
+                elapsed: self.scene.elapsed,
+                actions: self.recording.actions.clone(),
+                initial_maze: self.recording.initial_maze.clone(),
+            },
+            &self.scene.maze,
+        );
+        self.time_since_save = Duration::from_secs(0);
+        self.saved_actions = Some(self.recording.actions.len());
+    }
+
+    /// Every change of the maze is made by an action, so without new actions
+    /// the saved stage is still up to date.
+    fn changed_since_save(&self) -> bool {
+        self.saved_actions != Some(self.recording.actions.len())
+    }
+
+    fn save_recording(&self) {
+        if !recording_requested() {
+            return;
+        }
+        match self.recording.save() {
+            Ok(path) => eprintln!("Saved the recording to {}", path.display()),
+            Err(error) => eprintln!("Failed saving the recording: {}", error),
+        }
+    }
+
     fn notify_about_level_completion(&self) {
         level_completion_observer().lock().unwrap()
             .notify(LevelCompleted{
@@ -41,57 +127,83 @@ impl SceneScreen {
     }
 }
 
-enum Action {
+enum Input {
     Exit,
-    Move(Dir),
-    MoveBackwards,
-    UseHint,
+    Scene(Action),
     Nothing,
 }
 
+fn ctrl_pressed(keymod: Mod) -> bool {
+    keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD)
+}
+
+fn hex_direction(keycode: Keycode) -> Option<Dir> {
+    match keycode {
+        Keycode::W => Some(Dir::UP),
+        Keycode::E => Some(Dir::UP_RIGHT),
+        Keycode::D => Some(Dir::RIGHT),
+        Keycode::X => Some(Dir::DOWN),
+        Keycode::Z => Some(Dir::DOWN_LEFT),
+        Keycode::A => Some(Dir::LEFT),
+        _ => None,
+    }
+}
+
 impl Screen for SceneScreen {
     fn handle_event(&mut self, event: &sdl2::event::Event) -> Transition {
-        let action = match event {
+        let input = match event {
             Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                Action::Exit
+                Input::Exit
             },
             Event::KeyDown { keycode: Some(Keycode::Space), .. } => {
-                Action::MoveBackwards
+                Input::Scene(Action::MoveBackwards)
+            },
+            Event::KeyDown { keycode: Some(Keycode::Z), keymod, .. } if ctrl_pressed(*keymod) => {
+                Input::Scene(Action::Undo)
+            },
+            Event::KeyDown { keycode: Some(Keycode::Y), keymod, .. } if ctrl_pressed(*keymod) => {
+                Input::Scene(Action::Redo)
             },
             Event::KeyDown { keycode: Some(Keycode::Backquote), .. } => {
-                Action::UseHint
+                Input::Scene(Action::UseHint)
             },
             Event::KeyDown { keycode: Some(Keycode::Down), .. } => {
-                Action::Move(Dir::DOWN)
+                Input::Scene(Action::Move(Dir::DOWN))
             },
             Event::KeyDown { keycode: Some(Keycode::Right), .. } => {
-                Action::Move(Dir::RIGHT)
+                Input::Scene(Action::Move(Dir::RIGHT))
             },
             Event::KeyDown { keycode: Some(Keycode::Up), .. } => {
-                Action::Move(Dir::UP)
+                Input::Scene(Action::Move(Dir::UP))
             },
             Event::KeyDown { keycode: Some(Keycode::Left), .. } => {
-                Action::Move(Dir::LEFT)
+                Input::Scene(Action::Move(Dir::LEFT))
             },
-            _ => Action::Nothing
+            // Keys around "S" are laid out like neighbours of a hexagon.
+            Event::KeyDown { keycode: Some(keycode), .. } if hex_direction(*keycode).is_some() => {
+                Input::Scene(Action::Move(hex_direction(*keycode).unwrap()))
+            },
+            _ => Input::Nothing
         };
 
-        let move_result = match action {
-            Action::Exit => return Transition::GotoNow(MenuScreen::create()),
-            Action::Move(dir) => {
-                self.scene.try_move(dir)
-            },
-            Action::MoveBackwards => {
-                self.scene.try_move_towards_start()
-            },
-            Action::UseHint => {
-                self.scene.use_hint();
-                return Transition::Stay;
+        let action = match input {
+            Input::Exit => {
+                self.save();
+                self.save_recording();
+                return Transition::GotoNow(MenuScreen::create());
             },
-            Action::Nothing => return Transition::Stay,
+            Input::Scene(action) => action,
+            Input::Nothing => return Transition::Stay,
+        };
+        self.recording.actions.push(RecordedAction { time: self.scene.elapsed, action });
+        let move_result = match self.scene.apply(action) {
+            Some(move_result) => move_result,
+            None => return Transition::Stay,
         };
 
         if move_result == MoveResult::Finish {
+            get_persistent_state().lock().unwrap().forget_unfinished_stage();
+            self.save_recording();
             self.notify_about_level_completion();
             if self.autocontinue {
                 Transition::Goto(MenuScreen::create_and_autostart())
@@ -105,14 +217,46 @@ impl Screen for SceneScreen {
 
     fn update(&mut self, elapsed: Duration) -> Transition {
         self.scene.update(elapsed);
+        self.time_since_save += elapsed;
+        if self.time_since_save >= AUTOSAVE_INTERVAL {
+            if self.changed_since_save() {
+                self.save();
+            } else {
+                self.time_since_save = Duration::from_secs(0);
+            }
+        }
         Transition::Stay
     }
 
-    fn initialize(&mut self, canvas: &mut Canvas, _fonts: &Fonts) {
-        self.renderer.initialize(canvas);
+    fn initialize(&mut self, canvas: &mut Canvas, fonts: &Fonts) {
+        self.renderer.initialize(canvas, self.scene.maze.visibility_radius());
+
+        const MARGIN: i32 = 12;
+        let mut seed_code_text = TextView::new(
+            canvas,
+            &self.code.to_string(),
+            &fonts.small,
+            Color::RGB(96, 96, 96),
+            700
+        );
+        let viewport = canvas.viewport();
+        seed_code_text.set_dst_rect(Rect::new(
+            viewport.right() - seed_code_text.width() as i32 - MARGIN,
+            viewport.bottom() - seed_code_text.height() as i32 - MARGIN,
+            seed_code_text.width(),
+            seed_code_text.height()
+        ));
+        seed_code_text.show();
+        self.seed_code_text = Some(seed_code_text);
     }
 
     fn render(&self, canvas: &mut Canvas) {
         self.renderer.render(&self.scene, canvas);
+        self.seed_code_text.as_ref().unwrap().render(canvas);
+    }
+
+    fn quit(&mut self) {
+        self.save();
+        self.save_recording();
     }
 }
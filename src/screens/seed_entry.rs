@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use sdl2::rect::{Point, Rect};
+use sdl2::pixels::Color;
+
+use crate::screens::{
+    *,
+    loading::LoadingScreen,
+    menu::MenuScreen,
+    fading::FadingScreen,
+};
+use crate::ui::text_view::TextView;
+use crate::seed_code::{self, SeedCode, CODE_CHARS};
+
+const MAX_CODE_LENGTH: usize = 24;
+
+enum Message {
+    None,
+    InvalidCode,
+    LockedStage,
+}
+
+/// Lets the player type in a seed code to replay a specific maze.
+pub struct SeedEntryScreen {
+    /// The number of completed stages of every unlocked level.
+    unlocked: Vec<u32>,
+    input: String,
+    message: Message,
+
+    center: Point,
+    prompt_text: Option<TextView>,
+    invalid_code_text: Option<TextView>,
+    locked_stage_text: Option<TextView>,
+    // The code is drawn glyph by glyph, so its textures don't have to be
+    // recreated on every key press.
+    glyphs: HashMap<char, TextView>,
+}
+
+impl SeedEntryScreen {
+    pub fn create(unlocked: Vec<u32>) -> Box<dyn Screen> {
+        Box::new(FadingScreen::new(
+            Self {
+                unlocked,
+                input: String::new(),
+                message: Message::None,
+                center: Point::new(0, 0),
+                prompt_text: None,
+                invalid_code_text: None,
+                locked_stage_text: None,
+                glyphs: HashMap::new(),
+            },
+            Duration::from_millis(100),
+            Duration::from_millis(100)
+        ))
+    }
+
+    fn is_unlocked(&self, code: &SeedCode) -> bool {
+        self.unlocked.get(code.level).is_some_and(|&completed| code.stage <= completed)
+    }
+
+    fn start(&mut self) -> Transition {
+        match SeedCode::parse(&self.input) {
+            None => {
+                self.message = Message::InvalidCode;
+                Transition::Stay
+            },
+            Some(code) if !self.is_unlocked(&code) => {
+                self.message = Message::LockedStage;
+                Transition::Stay
+            },
+            Some(code) => Transition::Goto(Box::new(LoadingScreen::new(code, false))),
+        }
+    }
+
+    fn code_width(&self) -> u32 {
+        self.input.chars().map(|c| self.glyphs[&c].width()).sum()
+    }
+}
+
+impl Screen for SeedEntryScreen {
+    fn handle_event(&mut self, event: &Event) -> Transition {
+        match event {
+            Event::KeyDown { keycode: Some(Keycode::Escape), .. } =>
+                Transition::GotoNow(MenuScreen::create()),
+            Event::KeyDown { keycode: Some(Keycode::Return), .. } => self.start(),
+            Event::KeyDown { keycode: Some(Keycode::Backspace), .. } => {
+                self.input.pop();
+                self.message = Message::None;
+                Transition::Stay
+            },
+            Event::TextInput { text, .. } => {
+                for c in text.chars().filter_map(seed_code::normalize_char) {
+                    if self.input.len() < MAX_CODE_LENGTH {
+                        self.input.push(c);
+                    }
+                }
+                self.message = Message::None;
+                Transition::Stay
+            },
+            _ => Transition::Stay,
+        }
+    }
+
+    fn initialize(&mut self, canvas: &mut Canvas, fonts: &Fonts) {
+        const GAP: i32 = 60;
+
+        let center = canvas.viewport().center();
+        self.center = center;
+
+        let mut create_text = |text: &str, color: Color, y: i32| {
+            let mut view = TextView::new(canvas, text, &fonts.small, color, 975);
+            view.set_dst_rect(Rect::from_center(
+                Point::new(center.x(), y), view.width(), view.height()
+            ));
+            view.show();
+            view
+        };
+        let gray = Color::RGB(128, 128, 128);
+        let red = Color::RGB(192, 64, 64);
+        self.prompt_text = Some(create_text(
+            "Enter a seed code and press Enter", gray, center.y() - GAP
+        ));
+        self.invalid_code_text = Some(create_text(
+            "There is no such seed code", red, center.y() + GAP
+        ));
+        self.locked_stage_text = Some(create_text(
+            "This stage is not unlocked yet", red, center.y() + GAP
+        ));
+
+        for c in CODE_CHARS.chars() {
+            let mut glyph = TextView::new(
+                canvas, &c.to_string(), &fonts.default, Color::RGB(192, 192, 192), 975
+            );
+            glyph.show();
+            self.glyphs.insert(c, glyph);
+        }
+    }
+
+    fn render(&self, canvas: &mut Canvas) {
+        canvas.set_draw_color(Color::RGB(32, 32, 32));
+        canvas.clear();
+
+        self.prompt_text.as_ref().unwrap().render(canvas);
+        match self.message {
+            Message::None => {},
+            Message::InvalidCode => self.invalid_code_text.as_ref().unwrap().render(canvas),
+            Message::LockedStage => self.locked_stage_text.as_ref().unwrap().render(canvas),
+        }
+
+        let mut x = self.center.x() - self.code_width() as i32 / 2;
+        for c in self.input.chars() {
+            let glyph = &self.glyphs[&c];
+            glyph.render_to(canvas, Rect::new(
+                x, self.center.y() - glyph.height() as i32 / 2,
+                glyph.width(), glyph.height()
+            ));
+            x += glyph.width() as i32;
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use rand::Rng;
+
+use crate::levels::{LevelGenerator, GENERATORS};
+
+/// Crockford's base32 alphabet. It has no letters which are easy to confuse
+/// with digits, so codes can be read aloud and typed back safely.
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Characters which may appear in a printed code.
+pub const CODE_CHARS: &str = "0123456789ABCDEFGHJKMNPQRSTVWXYZ-";
+
+/// Random seeds are limited to this many bits to keep the codes short.
+const RANDOM_SEED_BITS: u32 = 40;
+
+/// Everything needed to generate a specific maze again.
+///
+/// Printed as `<level>-<stage>-<seed>`, where level and stage are decimal
+/// and the seed is in base32, e.g. `3-12-7QK2M0FA`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SeedCode {
+    /// Index of the level in `GENERATORS`.
+    pub level: usize,
+    pub stage: u32,
+    pub seed: u64,
+}
+
+impl SeedCode {
+    /// The maze everyone gets for the stage when playing through the levels.
+    pub fn default_for(level: usize, stage: u32) -> Self {
+        Self { level, stage, seed: u64::from(stage) }
+    }
+
+    /// A maze of the same difficulty as `default_for(level, stage)`,
+    /// but a different one.
+    pub fn random(level: usize, stage: u32) -> Self {
+        let seed = rand::thread_rng().gen_range(0, 1 << RANDOM_SEED_BITS);
+        Self { level, stage, seed }
+    }
+
+    pub fn generator(&self) -> &'static dyn LevelGenerator {
+        GENERATORS[self.level]
+    }
+
+    /// Parses a code ignoring case and the usual typos: `O` is read as `0`
+    /// and both `I` and `L` are read as `1`. Returns `None` if the code is
+    /// malformed or refers to a level which doesn't exist.
+    pub fn parse(code: &str) -> Option<Self> {
+        let code: String = code.trim().chars().map(normalize_char).collect::<Option<_>>()?;
+        let parts: Vec<&str> = code.split('-').collect();
+        if parts.len() != 3 {
+            return None;
+        }
+        let level = parts[0].parse().ok()?;
+        let stage = parts[1].parse().ok()?;
+        let seed = parse_base32(parts[2])?;
+        if level >= GENERATORS.len() {
+            return None;
+        }
+        Some(Self { level, stage, seed })
+    }
+}
+
+impl std::fmt::Display for SeedCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}-{}", self.level, self.stage, to_base32(self.seed))
+    }
+}
+
+/// Maps a typed character to the one it stands for in a printed code.
+/// Returns `None` for characters which can't appear in a code.
+pub fn normalize_char(c: char) -> Option<char> {
+    let c = match c.to_ascii_uppercase() {
+        'O' => '0',
+        'I' | 'L' => '1',
+        c => c,
+    };
+    if CODE_CHARS.contains(c) { Some(c) } else { None }
+}
+
+fn to_base32(mut value: u64) -> String {
+    let mut digits = Vec::new();
+    loop {
+        digits.push(ALPHABET[(value % 32) as usize]);
+        value /= 32;
+        if value == 0 {
+            break;
+        }
+    }
+    digits.reverse();
+    String::from_utf8(digits).unwrap()
+}
+
+fn parse_base32(s: &str) -> Option<u64> {
+    if s.is_empty() {
+        return None;
+    }
+    s.chars().try_fold(0u64, |value, c| {
+        let digit = ALPHABET.iter().position(|&d| char::from(d) == c)?;
+        value.checked_mul(32)?.checked_add(digit as u64)
+    })
+}
+
+#[test]
+fn test_seed_code() {
+    for &seed in &[0, 1, 31, 32, 1 << RANDOM_SEED_BITS, u64::MAX] {
+        let code = SeedCode { level: 2, stage: 13, seed };
+        assert_eq!(SeedCode::parse(&code.to_string()), Some(code));
+    }
+    assert_eq!(SeedCode::default_for(1, 5).to_string(), "1-5-5");
+    assert_eq!(SeedCode::parse(" 3-12-1o "), Some(SeedCode { level: 3, stage: 12, seed: 32 }));
+    assert_eq!(SeedCode::parse("3-12-iL"), Some(SeedCode { level: 3, stage: 12, seed: 33 }));
+
+    assert_eq!(SeedCode::parse("3-12"), None);
+    assert_eq!(SeedCode::parse("3-12-U"), None);
+    assert_eq!(SeedCode::parse("3--1"), None);
+    assert_eq!(SeedCode::parse("100-0-0"), None);
+    assert_eq!(SeedCode::parse("0-0-ZZZZZZZZZZZZZZ"), None);
+}
@@ -1,18 +1,19 @@
-use std::collections::{HashSet, HashMap};
+use std::collections::{HashSet, HashMap, VecDeque};
 
-use crate::geometry::{Dir, DIRECTIONS};
+use crate::geometry::{Dir, Topology};
 use crate::layer::Layer;
 use crate::visible_area::visible_area;
+use crate::utils::region::Region;
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct CoordInfo {
     /// Tells whether there is a cell in a subtree
     /// which is far enough from this one.
     ///
     /// Being `Some(escape)` means that new layer
     /// can be added with region around this cell copied
-    /// and generation started from `escape` is expected to be successful.
+    /// and generation started from `escape` reaches every cell of it.
     ///
     /// `None` means that there is no such cell in a subtree.
     pub escapable: Option<(i32, i32)>,
@@ -25,7 +26,7 @@ pub struct CoordInfo {
     pub came_from: Option<Dir>,
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Info {
     pub coords: HashMap<(i32, i32), CoordInfo>,
 
@@ -34,47 +35,132 @@ pub struct Info {
     pub leaf_escapables: Vec<(i32, i32)>,
 }
 
-fn escapable<I: Default>(c: (i32, i32), from: (i32, i32), layer: &Layer<I>) -> bool {
-    let visible_area = visible_area().shifted_by(from);
-    assert!(layer.has(c));
+/// Tells whether all the cells of a new layer are reachable if it is added
+/// with `area` around `from` copied from this layer.
+///
+/// Inside of the area the new layer has the same passages as this one,
+/// including the ones leading out of it. The rest is carved anew starting
+/// from the cells those passages lead to, so every cell connected to them
+/// becomes reachable. That is, the new layer is connected exactly when
+/// every cell is reachable from `from` if walls outside of the area are
+/// ignored.
+pub fn stays_connected<I: Default>(
+    layer: &Layer<I>,
+    from: (i32, i32),
+    area: &Region
+) -> bool {
+    let in_area = |c| area.cells().contains(&c);
+    reaches_all(layer, from, |c, dir| {
+        !(in_area(c) || in_area(c + dir)) || layer.passable(c, dir)
+    })
+}
 
-    if visible_area.cells().contains(&c)
-        || visible_area.boundary().contains(&c)
-    {
-        return false;
+/// Checks whether every cell of the layer can be reached from `from`
+/// moving only in the ways accepted by `is_open`.
+fn reaches_all<I: Default>(
+    layer: &Layer<I>,
+    from: (i32, i32),
+    is_open: impl Fn((i32, i32), Dir) -> bool
+) -> bool {
+    let mut reached = HashSet::new();
+    reached.insert(from);
+    let mut queue = VecDeque::new();
+    queue.push_back(from);
+    while let Some(c) = queue.pop_front() {
+        for &dir in layer.directions(c) {
+            let to = c + dir;
+            if layer.has(to) && is_open(c, dir) && reached.insert(to) {
+                queue.push_back(to);
+            }
+        }
     }
+    layer.cells().all(|c| reached.contains(&c))
+}
 
-    // For now, just check that layer contains 4 closest cells.
-    // It works well for simple layer shapes but more complex approach is
-    // needed for strong guarantees.
-    for &dir in &DIRECTIONS {
-        if !layer.has(c + dir) {
-            return false;
+/// Layers may contain loops, so traversal is done over a spanning tree.
+/// Breadth-first search is used for choosing it, so depth of every cell
+/// is the length of the shortest path to it.
+///
+/// Returns the direction to the parent for every cell of the tree.
+fn spanning_tree<I: Default>(
+    layer: &Layer<I>,
+    start: (i32, i32), from: Option<Dir>
+) -> HashMap<(i32, i32), Option<Dir>> {
+    let mut came_from = HashMap::new();
+    came_from.insert(start, None);
+
+    let mut queue = VecDeque::new();
+    queue.push_back(start);
+    while let Some(coord) = queue.pop_front() {
+        for &dir in layer.directions(coord) {
+            if coord == start && Some(dir) == from {
+                continue;
+            }
+            let to = coord + dir;
+            if layer.passable(coord, dir) && !came_from.contains_key(&to) {
+                came_from.insert(to, Some(dir.opposite()));
+                queue.push_back(to);
+            }
         }
     }
-
-    true
+    came_from
 }
 
+/// Traverses the layer from `start`, which is entered from `from`. Whether
+/// cells are escapable depends on the area visible from them, so it has
+/// to be traversed with the visibility radius of the maze.
 pub fn dfs<I: Default>(
     layer: &Layer<I>,
-    start: (i32, i32), from: Option<Dir>
+    start: (i32, i32), from: Option<Dir>,
+    visibility_radius: i32,
 ) -> Info {
-    let mut info = Info::default();
-    let mut visible_trace = HashSet::default();
+    traverse(layer, start, from, visibility_radius, false)
+}
+
+/// Same as `dfs`, but a cell is escapable the way it used to be before
+/// escapability was made exact: if some cell ahead of it lies outside of
+/// the area visible from it and has all of its neighbours in the layer.
+/// The cell found first is the escape. Legacy layers are built with it,
+/// so that their mazes don't change.
+pub fn legacy_dfs<I: Default>(
+    layer: &Layer<I>,
+    start: (i32, i32), from: Option<Dir>,
+    visibility_radius: i32,
+) -> Info {
+    traverse(layer, start, from, visibility_radius, true)
+}
+
+fn traverse<I: Default>(
+    layer: &Layer<I>,
+    start: (i32, i32), from: Option<Dir>,
+    visibility_radius: i32,
+    legacy: bool,
+) -> Info {
+    let is_connected = reaches_all(layer, start, |c, dir| layer.passable(c, dir));
+    let mut traversal = Traversal {
+        layer,
+        tree: spanning_tree(layer, start, from),
+        shape_is_connected: is_connected || reaches_all(layer, start, |_, _| true),
+        is_connected,
+        visibility_radius,
+        nearby: NearbyCells::new(layer.topology(), visibility_radius),
+        legacy,
+        visible_trace: HashSet::new(),
+        info: Info::default(),
+    };
 
     if let Some(from) = from {
-        dfs_impl(layer, start, from, &mut info, &mut visible_trace, 0);
+        traversal.run(start, from, 0);
     } else {
-        let back = Dir::DOWN;
-        dfs_impl(layer, start, back, &mut info, &mut visible_trace, 0);
+        // Any direction would do, but downwards is preferred when possible.
+        let directions = layer.directions(start);
+        let back = if directions.contains(&Dir::DOWN) { Dir::DOWN } else { directions[0] };
+        traversal.run(start, back, 0);
         if layer.passable(start, back) {
-            dfs_impl(
-                layer, start + back, back.opposite(),
-                &mut info, &mut visible_trace, 1
-            );
+            traversal.run(start + back, back.opposite(), 1);
         }
     };
+    let mut info = traversal.info;
     // There is no way to go backwards even if `from` is Some.
     // `from` only sets the direction of traversal.
     info.coords.get_mut(&start).unwrap().came_from = None;
@@ -82,57 +168,194 @@ pub fn dfs<I: Default>(
     info
 }
 
-fn dfs_impl<I: Default>(
-    layer: &Layer<I>,
-    coord: (i32, i32), from: Dir,
-    info: &mut Info,
-    visible_trace: &mut HashSet<(i32, i32)>,
-    depth: u32
-) {
-    let prev = info.coords.insert(coord, CoordInfo{
-        escapable: None,
-        has_escapable_below: false,
-        depth,
-        came_from: Some(from)
-    });
-    if prev.is_some() {
-        panic!("Layer contains a loop");
-    }
+/// Tells which cells see each other. A cell is near another one if it lies
+/// in the area visible from it or on the boundary of that area.
+///
+/// Visible areas of all the cells are the same up to a shift, except for
+/// triangles pointing up and down, so they are stored relative to a cell
+/// of each kind.
+#[allow(clippy::type_complexity)]
+struct NearbyCells {
+    topology: Topology,
+    /// A cell of each kind.
+    origins: Vec<(i32, i32)>,
+    /// For every kind, the offsets of the cells near the cell of that kind.
+    areas: Vec<HashSet<(i32, i32)>>,
+    /// For every move, the cells which the moving cell stops being near,
+    /// given by their kind and their offset back from the moving cell.
+    left_behind: HashMap<Dir, Vec<(usize, (i32, i32))>>,
+}
 
-    visible_trace.retain(|c| {
-        if escapable(coord, /* from= */ *c, &layer) {
-            info.coords.get_mut(c).unwrap().escapable = Some(coord);
-            false
-        } else {
-            true
+impl NearbyCells {
+    fn new(topology: Topology, visibility_radius: i32) -> Self {
+        let mut origins = vec![(0, 0)];
+        if topology.directions((1, 0)) != topology.directions((0, 0)) {
+            origins.push((1, 0));
         }
-    });
-    visible_trace.insert(coord);
+        let areas: Vec<HashSet<_>> = origins.iter().map(|&origin| {
+            let area = visible_area(topology, visibility_radius, origin);
+            area.cells().iter().chain(area.boundary().iter())
+                .map(|&(x, y)| (x - origin.0, y - origin.1))
+                .collect()
+        }).collect();
 
-    let mut dir = from.rotate_clockwise();
-    while dir != from {
-        if layer.passable(coord, dir) {
-            let to = coord + dir;
-            dfs_impl(
-                layer,
-                to, dir.opposite(),
-                info, visible_trace, depth + 1
-            );
-            if info.coords[&to].has_escapable_below
-                || info.coords[&to].escapable.is_some()
+        let mut left_behind: HashMap<Dir, Vec<_>> = HashMap::new();
+        for &origin in &origins {
+            for &dir in topology.directions(origin) {
+                left_behind.entry(dir).or_insert_with(|| {
+                    areas.iter().enumerate().flat_map(|(kind, offsets)| {
+                        offsets.iter()
+                            .filter(move |&&offset| !offsets.contains(&(offset + dir)))
+                            .map(move |&offset| (kind, offset))
+                    }).collect()
+                });
+            }
+        }
+        Self { topology, origins, areas, left_behind }
+    }
+
+    fn kind(&self, coord: (i32, i32)) -> usize {
+        let directions = self.topology.directions(coord);
+        self.origins.iter()
+            .position(|&origin| self.topology.directions(origin) == directions)
+            .unwrap()
+    }
+
+    fn is_near(&self, cell: (i32, i32), coord: (i32, i32)) -> bool {
+        self.areas[self.kind(coord)].contains(&(cell.0 - coord.0, cell.1 - coord.1))
+    }
+
+    /// Cells which are near `coord` but not near `coord + dir`.
+    fn left_behind(&self, coord: (i32, i32), dir: Dir) -> impl Iterator<Item=(i32, i32)> + '_ {
+        self.left_behind[&dir].iter()
+            .map(move |&(kind, (x, y))| (kind, (coord.0 - x, coord.1 - y)))
+            .filter(move |&(kind, c)| self.origins.len() == 1 || self.kind(c) == kind)
+            .map(|(_, c)| c)
+    }
+}
+
+struct Traversal<'a, I: Default> {
+    layer: &'a Layer<I>,
+    tree: HashMap<(i32, i32), Option<Dir>>,
+    shape_is_connected: bool,
+    /// If every cell is reachable, every part of the layer outside of an area
+    /// is joined to the area by a passage, so new layers always stay connected.
+    is_connected: bool,
+    visibility_radius: i32,
+    nearby: NearbyCells,
+    /// Whether escapes are found as `legacy_dfs` does.
+    legacy: bool,
+    /// The cells on the path to the current one which don't have an escape
+    /// yet. Unless the traversal is a legacy one, all of them are near
+    /// the current cell.
+    visible_trace: HashSet<(i32, i32)>,
+    info: Info,
+}
+
+impl<I: Default> Traversal<'_, I> {
+    /// Walks the subtree of `root` with an explicit stack, as paths in large
+    /// layers are too long for recursion.
+    fn run(&mut self, root: (i32, i32), from: Dir, depth: u32) {
+        let topology = self.layer.topology();
+        self.enter(root, from, depth);
+        // Every cell on the path along with the next direction to try.
+        let mut stack = vec![(root, from, topology.rotate_clockwise(root, from))];
+        while let Some((coord, from, dir)) = stack.last_mut() {
+            let (coord, from) = (*coord, *from);
+            if *dir == from {
+                stack.pop();
+                self.leave(coord);
+                if let Some(&(parent, _, _)) = stack.last() {
+                    let coord_info = &self.info.coords[&coord];
+                    if coord_info.has_escapable_below || coord_info.escapable.is_some() {
+                        self.info.coords.get_mut(&parent).unwrap().has_escapable_below = true;
+                    }
+                }
+                continue;
+            }
+
+            let to_dir = *dir;
+            *dir = topology.rotate_clockwise(coord, to_dir);
+            let to = coord + to_dir;
+            if self.layer.passable(coord, to_dir)
+                && self.tree.get(&to) == Some(&Some(to_dir.opposite()))
             {
-                info.coords.get_mut(&coord).unwrap().has_escapable_below = true;
+                if !self.legacy {
+                    self.move_trace(coord, to_dir);
+                }
+                let depth = self.info.coords[&coord].depth + 1;
+                self.enter(to, to_dir.opposite(), depth);
+                let back = to_dir.opposite();
+                stack.push((to, back, topology.rotate_clockwise(to, back)));
             }
         }
-        dir = dir.rotate_clockwise();
     }
 
-    let coord_info = &info.coords[&coord];
-    if coord_info.escapable.is_some() && !coord_info.has_escapable_below {
-        info.leaf_escapables.push(coord);
+    fn enter(&mut self, coord: (i32, i32), from: Dir, depth: u32) {
+        let prev = self.info.coords.insert(coord, CoordInfo{
+            escapable: None,
+            has_escapable_below: false,
+            depth,
+            came_from: Some(from)
+        });
+        if prev.is_some() {
+            panic!("Spanning tree contains a loop");
+        }
+        if self.legacy {
+            self.find_legacy_escapes(coord);
+        }
+        self.visible_trace.insert(coord);
     }
 
-    visible_trace.remove(&coord);
+    /// Makes `coord` the escape of the cells on the trace which it is
+    /// escapable from in the legacy way. Every cell on the trace has to be
+    /// checked, as the path may come back near it.
+    fn find_legacy_escapes(&mut self, coord: (i32, i32)) {
+        let layer = self.layer;
+        if !layer.directions(coord).iter().all(|&dir| layer.has(coord + dir)) {
+            return;
+        }
+        let nearby = &self.nearby;
+        let coords = &mut self.info.coords;
+        self.visible_trace.retain(|&c| {
+            if nearby.is_near(coord, c) {
+                return true;
+            }
+            coords.get_mut(&c).unwrap().escapable = Some(coord);
+            false
+        });
+    }
+
+    fn leave(&mut self, coord: (i32, i32)) {
+        let coord_info = &self.info.coords[&coord];
+        if coord_info.escapable.is_some() && !coord_info.has_escapable_below {
+            self.info.leaf_escapables.push(coord);
+        }
+        self.visible_trace.remove(&coord);
+    }
+
+    /// Finds an escape for the cells on the trace which are left behind when
+    /// going from `coord` in `dir`. Every cell on the trace is near `coord`,
+    /// so only the cells which stop being near have to be checked.
+    ///
+    /// Whether a new layer added at a cell stays connected doesn't depend on
+    /// the escape, so the first cell found outside of the area visible from
+    /// it decides whether it is escapable.
+    fn move_trace(&mut self, coord: (i32, i32), dir: Dir) {
+        let escape = coord + dir;
+        for c in self.nearby.left_behind(coord, dir) {
+            if !self.visible_trace.remove(&c) {
+                continue;
+            }
+            if self.is_connected || self.shape_is_connected
+                && stays_connected(
+                    self.layer, c, &visible_area(self.layer.topology(), self.visibility_radius, c)
+                )
+            {
+                self.info.coords.get_mut(&c).unwrap().escapable = Some(escape);
+            }
+        }
+    }
 }
 
 pub fn get_path_to(from: (i32, i32), to: (i32, i32), info: &Info) -> Vec<Dir> {
@@ -147,3 +370,52 @@ pub fn get_path_to(from: (i32, i32), to: (i32, i32), info: &Info) -> Vec<Dir> {
 
     result
 }
+
+#[test]
+fn test_dfs_with_loops() {
+    use itertools::Itertools;
+    use crate::visible_area::DEFAULT_VISIBILITY_RADIUS;
+
+    let shape = (0..5).cartesian_product(0..5).collect::<Vec<_>>();
+    let mut layer = Layer::<()>::from_shape(&shape);
+    for &(x, y) in &shape {
+        if x < 4 {
+            layer.join((x, y), Dir::RIGHT);
+        }
+        if y < 4 {
+            layer.join((x, y), Dir::DOWN);
+        }
+    }
+
+    let info = dfs(&layer, (0, 0), None, DEFAULT_VISIBILITY_RADIUS);
+    assert_eq!(info.coords.len(), shape.len());
+    for (&(x, y), coord_info) in &info.coords {
+        assert_eq!(coord_info.depth, (x + y) as u32);
+        assert_eq!(get_path_to((0, 0), (x, y), &info).len(), (x + y) as usize);
+    }
+
+    // Going back from the start is not allowed but the cells behind
+    // are still reachable through the loops.
+    let info = dfs(&layer, (2, 2), Some(Dir::LEFT), DEFAULT_VISIBILITY_RADIUS);
+    assert_eq!(info.coords[&(1, 2)].depth, 3);
+}
+
+#[test]
+fn test_stays_connected() {
+    use crate::visible_area::DEFAULT_VISIBILITY_RADIUS;
+
+    let shape: Vec<_> = (-30..=30).map(|x| (x, 0)).collect();
+    let mut layer = Layer::<()>::from_shape(&shape);
+    for x in -30..30 {
+        if x != 5 {
+            layer.join((x, 0), Dir::RIGHT);
+        }
+    }
+    // Walls outside of the visible area don't matter.
+    layer.separate((20, 0), Dir::RIGHT);
+    let area = visible_area(layer.topology(), DEFAULT_VISIBILITY_RADIUS, (0, 0));
+    assert!(!stays_connected(&layer, (0, 0), &area));
+
+    layer.join((5, 0), Dir::RIGHT);
+    assert!(stays_connected(&layer, (0, 0), &area));
+}
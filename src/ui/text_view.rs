@@ -66,21 +66,27 @@ impl TextView {
     }
 
     pub fn render(&self, canvas: &mut Canvas) {
+        self.render_to(canvas, self.dst_rect);
+    }
+
+    /// Renders the text into `dst_rect` instead of the one set with
+    /// `set_dst_rect`. Useful for drawing the same text in several places.
+    pub fn render_to(&self, canvas: &mut Canvas, dst_rect: Rect) {
         use std::f32::consts::PI;
         match self.state {
             TextViewState::Hidden => {},
             TextViewState::Shown => {
-                canvas.copy(&self.texture.borrow(), None, self.dst_rect).unwrap();
+                canvas.copy(&self.texture.borrow(), None, dst_rect).unwrap();
             },
             TextViewState::Pulsating(phase, _, from_opacity, to_opacity) => {
                 let visibility = (1. - (phase * PI * 2.).cos()) / 2.;
-                assert!(0. <= visibility && visibility <= 1.);
+                assert!((0. ..=1.).contains(&visibility));
                 let int_opacity = from_opacity + (
                     f32::from(to_opacity - from_opacity) * visibility
                 ) as u8;
                 let mut texture = self.texture.borrow_mut();
                 texture.set_alpha_mod(int_opacity);
-                canvas.copy(&texture, None, self.dst_rect).unwrap();
+                canvas.copy(&texture, None, dst_rect).unwrap();
             },
         }
     }
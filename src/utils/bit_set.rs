@@ -0,0 +1,47 @@
+/// Fixed-size set of bits packed into words.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct BitSet {
+    words: Vec<u64>,
+}
+
+impl BitSet {
+    pub fn new(len: usize) -> Self {
+        Self {
+            words: vec![0; len.div_ceil(64)],
+        }
+    }
+
+    /// The number of bits which can be stored, rounded up to whole words.
+    pub fn capacity(&self) -> usize {
+        self.words.len() * 64
+    }
+
+    pub fn get(&self, index: usize) -> bool {
+        self.words[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    pub fn set(&mut self, index: usize, value: bool) {
+        if value {
+            self.words[index / 64] |= 1 << (index % 64);
+        } else {
+            self.words[index / 64] &= !(1 << (index % 64));
+        }
+    }
+
+    #[cfg(feature = "bench")]
+    pub fn heap_size(&self) -> usize {
+        self.words.capacity() * std::mem::size_of::<u64>()
+    }
+}
+
+#[test]
+fn test_bit_set() {
+    let mut bits = BitSet::new(130);
+    bits.set(0, true);
+    bits.set(64, true);
+    bits.set(129, true);
+    assert!(bits.get(0) && bits.get(64) && bits.get(129));
+    assert!(!bits.get(1) && !bits.get(63) && !bits.get(128));
+    bits.set(64, false);
+    assert!(!bits.get(64));
+}
@@ -0,0 +1,81 @@
+use std::cell::Cell;
+
+/// Union-find over elements `0..len`.
+///
+/// Sets can also be taken apart: every element of a set can be reset
+/// to a singleton and then united again in a different way.
+#[derive(Debug, Clone, Default)]
+pub struct DisjointSets {
+    // Indices are stored as `u32` to halve the memory usage.
+    parent: Vec<Cell<u32>>,
+    rank: Vec<u8>,
+}
+
+impl DisjointSets {
+    pub fn new(len: usize) -> Self {
+        Self {
+            parent: (0..len as u32).map(Cell::new).collect(),
+            rank: vec![0; len],
+        }
+    }
+
+    pub fn find(&self, element: usize) -> usize {
+        let mut root = element as u32;
+        while self.parent[root as usize].get() != root {
+            root = self.parent[root as usize].get();
+        }
+        // Path compression.
+        let mut element = element as u32;
+        while element != root {
+            element = self.parent[element as usize].replace(root);
+        }
+        root as usize
+    }
+
+    pub fn union(&mut self, a: usize, b: usize) {
+        let a = self.find(a);
+        let b = self.find(b);
+        if a == b {
+            return;
+        }
+        let (child, root) = if self.rank[a] < self.rank[b] { (a, b) } else { (b, a) };
+        self.parent[child].set(root as u32);
+        if self.rank[child] == self.rank[root] {
+            self.rank[root] += 1;
+        }
+    }
+
+    pub fn equiv(&self, a: usize, b: usize) -> bool {
+        self.find(a) == self.find(b)
+    }
+
+    #[cfg(feature = "bench")]
+    pub fn heap_size(&self) -> usize {
+        self.parent.capacity() * std::mem::size_of::<Cell<u32>>() + self.rank.capacity()
+    }
+
+    /// Makes `element` a singleton. Other elements may still point to it,
+    /// so this must be done for every element of the set at once.
+    pub fn reset(&mut self, element: usize) {
+        self.parent[element].set(element as u32);
+        self.rank[element] = 0;
+    }
+}
+
+#[test]
+fn test_disjoint_sets() {
+    let mut sets = DisjointSets::new(5);
+    sets.union(0, 1);
+    sets.union(1, 2);
+    sets.union(3, 4);
+    assert!(sets.equiv(0, 2));
+    assert!(!sets.equiv(2, 3));
+
+    for element in 0..3 {
+        sets.reset(element);
+    }
+    sets.union(0, 2);
+    assert!(sets.equiv(0, 2));
+    assert!(!sets.equiv(0, 1));
+    assert!(sets.equiv(3, 4));
+}
@@ -1,3 +1,5 @@
 pub mod region;
 pub mod tuple_arithmetic;
 pub mod persistent_state;
+pub mod disjoint_sets;
+pub mod bit_set;
@@ -1,16 +1,39 @@
 use std::collections::HashMap;
 use std::sync::Mutex;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use crate::observers::level_completion_observer;
+use crate::maze::{Maze, storage};
+use crate::scene::RecordedAction;
+use crate::seed_code::SeedCode;
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct Progress {
     pub completed_stages: HashMap<String, u32>,
 }
 
+/// A stage the player left before reaching the finish.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct UnfinishedStage {
+    pub code: SeedCode,
+    pub autocontinue: bool,
+    /// Time spent playing the stage.
+    pub elapsed: Duration,
+    /// Actions made so far, so that the recording of the stage goes on.
+    #[serde(default)]
+    pub actions: Vec<RecordedAction>,
+    /// The maze the actions were made in, in the binary format. It is kept
+    /// in a separate file and is empty if that file is missing.
+    #[serde(skip)]
+    pub initial_maze: Vec<u8>,
+}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct PersistentState {
     pub progress: Progress,
+    /// The maze of the stage is kept in a separate binary file.
+    #[serde(default)]
+    pub unfinished_stage: Option<UnfinishedStage>,
 }
 
 impl Progress {
@@ -53,7 +76,46 @@ impl PersistentState {
         Self {
             progress: Progress {
                 completed_stages: HashMap::new()
-            }
+            },
+            unfinished_stage: None,
+        }
+    }
+
+    /// Replaces the previously saved unfinished stage. Failures are only
+    /// reported, the game goes on without the stage saved.
+    pub fn save_unfinished_stage(&mut self, stage: UnfinishedStage, maze: &Maze) {
+        let path = Self::unfinished_maze_path();
+        let written = std::fs::create_dir_all(path.parent().unwrap())
+            .and_then(|()| std::fs::write(&path, storage::to_binary(maze)))
+            .and_then(|()| std::fs::write(Self::initial_maze_path(), &stage.initial_maze));
+        if let Err(error) = written {
+            eprintln!("Failed saving the unfinished stage to {}: {}", path.display(), error);
+            return;
+        }
+        self.unfinished_stage = Some(stage);
+        self.flush();
+    }
+
+    /// Returns `None` if there is no unfinished stage or its maze
+    /// can't be loaded, which happens after format changes.
+    pub fn load_unfinished_stage(&self) -> Option<(UnfinishedStage, Maze)> {
+        let mut stage = self.unfinished_stage.clone()?;
+        let bytes = std::fs::read(Self::unfinished_maze_path()).ok()?;
+        stage.initial_maze = std::fs::read(Self::initial_maze_path()).unwrap_or_default();
+        match storage::from_binary(&bytes) {
+            Ok(maze) => Some((stage, maze)),
+            Err(error) => {
+                eprintln!("Failed loading the unfinished stage: {}", error);
+                None
+            },
+        }
+    }
+
+    pub fn forget_unfinished_stage(&mut self) {
+        if self.unfinished_stage.take().is_some() {
+            let _ = std::fs::remove_file(Self::unfinished_maze_path());
+            let _ = std::fs::remove_file(Self::initial_maze_path());
+            self.flush();
         }
     }
 
@@ -63,6 +125,18 @@ impl PersistentState {
         path
     }
 
+    fn unfinished_maze_path() -> PathBuf {
+        let mut path = dirs::data_dir().unwrap();
+        path.push("amazeing/unfinished_stage.maze");
+        path
+    }
+
+    fn initial_maze_path() -> PathBuf {
+        let mut path = dirs::data_dir().unwrap();
+        path.push("amazeing/unfinished_stage_start.maze");
+        path
+    }
+
     fn flush(&self) {
         use std::io::Write;
         use std::fs::{File, create_dir_all};
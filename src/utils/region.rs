@@ -1,5 +1,5 @@
 use std::collections::HashSet;
-use itertools::Itertools;
+use crate::geometry::Topology;
 use crate::geometry_sets::dilate;
 
 #[derive(Debug, Clone)]
@@ -10,13 +10,7 @@ pub struct Region {
 
 impl From<HashSet<(i32, i32)>> for Region {
     fn from(cells: HashSet<(i32, i32)>) -> Self {
-        let mut boundary = dilate(&cells, &(-1..=1).cartesian_product(-1..=1).collect());
-        for cell in &cells {
-            boundary.remove(cell);
-        }
-        boundary.shrink_to_fit();
-
-        Region{cells, boundary}
+        Region::with_topology(cells, Topology::Square)
     }
 }
 
@@ -27,6 +21,19 @@ impl From<&HashSet<(i32, i32)>> for Region {
 }
 
 impl Region {
+    /// Boundary consists of the cells outside of the region which share
+    /// at least a corner with it.
+    pub fn with_topology(cells: HashSet<(i32, i32)>, topology: Topology) -> Self {
+        let offsets = topology.touching_offsets().into_iter().collect();
+        let mut boundary = dilate(&cells, &offsets);
+        for cell in &cells {
+            boundary.remove(cell);
+        }
+        boundary.shrink_to_fit();
+
+        Region{cells, boundary}
+    }
+
     pub fn cells(&self) -> &HashSet<(i32, i32)> {
         &self.cells
     }
@@ -1,20 +1,57 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+use itertools::Itertools;
 use crate::utils::region::Region;
+use crate::geometry::Topology;
 use crate::geometry_sets::make_circle;
 
-// This value is used by both renderer and maze builder.
-// That's why using global value is simplier than passing it through call stack.
-pub fn visibility_radius() -> i32 {
-    12
+/// Visibility radius of levels which don't set their own.
+pub const DEFAULT_VISIBILITY_RADIUS: i32 = 12;
+
+fn make_visible_area(topology: Topology, origin: (i32, i32), radius: i32) -> Region {
+    let cells: HashSet<_> = match topology {
+        Topology::Square => make_circle(radius).collect(),
+        // Cells of other topologies are not laid out along the axes,
+        // so the distance is measured on the screen.
+        Topology::Hex | Topology::Triangle => {
+            let center = topology.center(origin);
+            (-2 * radius..=2 * radius).cartesian_product(-2 * radius..=2 * radius)
+                .map(|(x, y)| (origin.0 + x, origin.1 + y))
+                .filter(|&cell| {
+                    let (x, y) = topology.center(cell);
+                    (x - center.0).powi(2) + (y - center.1).powi(2) < (radius as f32).powi(2)
+                })
+                .collect()
+        },
+    };
+    Region::with_topology(cells, topology)
 }
 
-// This region is used by both renderer and maze builder too.
-// Moreover it is expensive to copy, so reference to the same object is used.
-pub fn visible_area() -> &'static Region {
+/// Topology, origin and radius of a visible area.
+type AreaKey = (Topology, (i32, i32), i32);
+
+// This region is used by both renderer and maze builder.
+// Moreover it is expensive to build, so it is computed once for every topology
+// and radius and then shifted to `coord`.
+pub fn visible_area(topology: Topology, radius: i32, coord: (i32, i32)) -> Region {
     lazy_static! {
-        static ref VISIBLE_AREA: Region = {
-            make_circle(visibility_radius()).collect::<HashSet<_>>().into()
-        };
+        static ref VISIBLE_AREAS: RwLock<HashMap<AreaKey, Region>> =
+            RwLock::new(HashMap::new());
+    }
+    // Shifting a triangle by an odd offset flips it, so triangles
+    // pointing up and down need their own areas.
+    let origin = if topology.directions(coord) == topology.directions((0, 0)) {
+        (0, 0)
+    } else {
+        (1, 0)
+    };
+    let key = (topology, origin, radius);
+    let shift = (coord.0 - origin.0, coord.1 - origin.1);
+    if let Some(region) = VISIBLE_AREAS.read().unwrap().get(&key) {
+        return region.shifted_by(shift);
     }
-    &VISIBLE_AREA
+    let region = make_visible_area(topology, origin, radius);
+    let shifted = region.shifted_by(shift);
+    VISIBLE_AREAS.write().unwrap().insert(key, region);
+    shifted
 }